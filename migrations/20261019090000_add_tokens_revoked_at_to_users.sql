@@ -0,0 +1,2 @@
+-- Tokens issued before this instant are rejected by the JWT validator
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_revoked_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,9 @@
+-- Create queue blobs table for payloads offloaded from pgmq messages
+CREATE TABLE IF NOT EXISTS queue_blobs (
+    id UUID PRIMARY KEY,
+    queue_name VARCHAR(60) NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_queue_blobs_queue_name ON queue_blobs(queue_name);
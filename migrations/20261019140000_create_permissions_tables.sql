@@ -0,0 +1,19 @@
+-- Create permissions catalog and role permissions join table
+-- role_permissions references names so it can be seeded from RON files
+CREATE TABLE IF NOT EXISTS catalogs.permissions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid (),
+    name VARCHAR(100) UNIQUE NOT NULL,
+    description VARCHAR(255),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS catalogs.role_permissions (
+    role_name VARCHAR(50) NOT NULL,
+    permission_name VARCHAR(100) NOT NULL,
+    assigned_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (role_name, permission_name),
+    FOREIGN KEY (role_name) REFERENCES catalogs.roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (permission_name) REFERENCES catalogs.permissions(name) ON DELETE CASCADE ON UPDATE CASCADE
+);
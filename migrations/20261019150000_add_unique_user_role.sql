@@ -0,0 +1,9 @@
+-- Users can hold several roles, but each role only once
+DELETE FROM users_role a
+USING users_role b
+WHERE a.user_id = b.user_id
+  AND a.role_id = b.role_id
+  AND a.ctid > b.ctid;
+
+ALTER TABLE users_role
+    ADD CONSTRAINT users_role_user_id_role_id_key UNIQUE (user_id, role_id);
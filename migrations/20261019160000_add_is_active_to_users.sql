@@ -0,0 +1,4 @@
+-- Deactivated users keep their data but can no longer authenticate
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_active BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_users_created_at ON users(created_at);
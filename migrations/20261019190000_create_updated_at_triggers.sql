@@ -0,0 +1,25 @@
+-- Keep updated_at current on every update
+CREATE OR REPLACE FUNCTION set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Bookkeeping columns (token revocation, failed logins) do not count as a change of the user
+CREATE TRIGGER users_set_updated_at
+    BEFORE UPDATE OF username, email, full_name, password_hash, is_active, email_verified_at
+    ON users
+    FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER roles_set_updated_at
+    BEFORE UPDATE ON catalogs.roles
+    FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER permissions_set_updated_at
+    BEFORE UPDATE ON catalogs.permissions
+    FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER workflow_instances_set_updated_at
+    BEFORE UPDATE ON workflow_instances
+    FOR EACH ROW EXECUTE FUNCTION set_updated_at();
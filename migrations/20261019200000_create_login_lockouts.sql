@@ -0,0 +1,15 @@
+-- Temporary account lockouts after repeated failed logins
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS locked_until TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS lockout_count INTEGER NOT NULL DEFAULT 0;
+
+-- Failed logins per client IP, whatever account they targeted
+CREATE TABLE IF NOT EXISTS login_ip_attempts (
+    ip VARCHAR(45) PRIMARY KEY,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMP WITH TIME ZONE,
+    lockout_count INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_ip_attempts_last_failed_at ON login_ip_attempts(last_failed_at);
@@ -0,0 +1,6 @@
+-- Store password reset tokens as SHA-256 hashes. Outstanding links keep working since
+-- their hashes are computed in place
+UPDATE password_reset_tokens SET token = encode(sha256(convert_to(token, 'UTF8')), 'hex');
+
+ALTER TABLE password_reset_tokens RENAME COLUMN token TO token_hash;
+ALTER INDEX idx_password_reset_tokens_token RENAME TO idx_password_reset_tokens_token_hash;
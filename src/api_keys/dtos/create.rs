@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::api_keys::entities::ApiKey;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A new key, the only response that carries its secret.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
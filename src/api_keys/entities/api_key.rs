@@ -0,0 +1,45 @@
+use std::future::{Ready, ready};
+
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, dev::Payload, error};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_by: Option<Uuid>,
+    pub name: String,
+    /// First characters of the key, enough to recognise it in listings and logs
+    pub prefix: String,
+    pub permissions: Vec<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The API key that authenticated the request.
+///
+/// Stored in the request extensions by the validator, next to the permissions of the key
+/// attached as authorities.
+#[derive(Debug, Clone)]
+pub struct ApiKeyScope {
+    pub id: Uuid,
+    pub user_id: Uuid,
+}
+
+impl FromRequest for ApiKeyScope {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<ApiKeyScope>()
+                .cloned()
+                .ok_or_else(|| error::ErrorUnauthorized("API key not specified")),
+        )
+    }
+}
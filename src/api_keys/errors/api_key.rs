@@ -0,0 +1,37 @@
+use actix_failwrap::ErrorResponse;
+use thiserror::Error;
+
+#[derive(Debug, ErrorResponse, Error)]
+pub enum ApiKeyErrors {
+    #[error("Invalid request")]
+    #[status_code(400)]
+    InvalidRequest,
+
+    #[error("The key owner does not hold every requested permission")]
+    #[status_code(400)]
+    PermissionNotHeld,
+
+    #[error("Expiry must be in the future")]
+    #[status_code(400)]
+    ExpiryInPast,
+
+    #[error("Invalid or expired token")]
+    #[status_code(401)]
+    InvalidToken,
+
+    #[error("Access denied")]
+    #[status_code(403)]
+    Forbidden,
+
+    #[error("User not found")]
+    #[status_code(404)]
+    UserNotFound,
+
+    #[error("API key not found")]
+    #[status_code(404)]
+    KeyNotFound,
+
+    #[error("Database error")]
+    #[status_code(500)]
+    DatabaseError,
+}
@@ -0,0 +1,15 @@
+pub mod entities {
+    mod api_key;
+    pub use api_key::*;
+}
+
+mod dtos;
+pub use dtos::*;
+
+pub mod errors;
+
+mod routes;
+pub use routes::{admin_config as admin_routes, api_config as api_routes};
+
+mod service;
+pub use service::*;
@@ -0,0 +1,152 @@
+use actix_failwrap::proof_route;
+use actix_web::{
+    HttpResponse, Result,
+    web::{self, Data, Json, Path},
+};
+use actix_web_grants::authorities::{AuthDetails, AuthoritiesCheck};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    api_keys::{CreateApiKeyRequest, entities::ApiKey, errors::api_key::ApiKeyErrors},
+    middlewares::jwt::Claims,
+    users::permissions::API_KEYS_MANAGE,
+};
+
+/// Configure API key routes of the current user, mounted under the `/api` scope
+///
+/// These routes need a user access token, an API key cannot manage keys.
+///
+/// `POST` `/api/me/api-keys` - Create an API key, the response is the only one carrying it
+///
+/// Every requested permission must be held by the user. Create API Key Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct CreateApiKeyRequest {
+///     #[validate(length(min = 1, max = 100))]
+///     pub name: String,
+///     #[serde(default)]
+///     pub permissions: Vec<String>,
+///     pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+/// }
+/// ```
+///
+/// `GET` `/api/me/api-keys` - List the API keys of the current user
+///
+/// API Key entity:
+/// ```ignore
+/// #[derive(Debug, Serialize, FromRow)]
+/// pub struct ApiKey {
+///     pub id: Uuid,
+///     pub user_id: Uuid,
+///     pub created_by: Option<Uuid>,
+///     pub name: String,
+///     pub prefix: String,
+///     pub permissions: Vec<String>,
+///     pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+///     pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+///     pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+///     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+/// }
+/// ```
+///
+/// `DELETE` `/api/me/api-keys/{id}` - Revoke an API key of the current user
+pub fn api_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_own_api_key)
+        .service(list_own_api_keys)
+        .service(revoke_own_api_key);
+}
+
+/// Configure API key admin routes, mounted under the authenticated `/admin` scope
+///
+/// Every route requires the `api_keys:manage` permission and a user access token.
+///
+/// `POST` `/admin/users/{id}/api-keys` - Create an API key for a user, with permissions
+/// the user holds
+///
+/// `GET` `/admin/users/{id}/api-keys` - List the API keys of a user
+///
+/// `DELETE` `/admin/api-keys/{id}` - Revoke any API key
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_user_api_key)
+        .service(list_user_api_keys)
+        .service(revoke_api_key);
+}
+
+#[proof_route("POST /me/api-keys")]
+async fn create_own_api_key(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<CreateApiKeyRequest>,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    body.validate().map_err(|_| ApiKeyErrors::InvalidRequest)?;
+    let created = ApiKey::create(&state, claims.user_id, claims.user_id, &body).await?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[proof_route("GET /me/api-keys")]
+async fn list_own_api_keys(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    let keys = ApiKey::list(&state, claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+#[proof_route("DELETE /me/api-keys/{id}")]
+async fn revoke_own_api_key(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(KeyNotFound)] id: Path<Uuid>,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    let key = ApiKey::revoke(&state, id.into_inner(), Some(claims.user_id)).await?;
+    Ok(HttpResponse::Ok().json(key))
+}
+
+#[proof_route("POST /users/{id}/api-keys")]
+async fn create_user_api_key(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(UserNotFound)] id: Path<Uuid>,
+    #[error_override(InvalidRequest)] body: Json<CreateApiKeyRequest>,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    if !auth.has_authority(API_KEYS_MANAGE) {
+        return Err(ApiKeyErrors::Forbidden);
+    }
+
+    body.validate().map_err(|_| ApiKeyErrors::InvalidRequest)?;
+    let created = ApiKey::create(&state, id.into_inner(), claims.user_id, &body).await?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[proof_route("GET /users/{id}/api-keys")]
+async fn list_user_api_keys(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] _claims: Claims,
+    #[error_override(UserNotFound)] id: Path<Uuid>,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    if !auth.has_authority(API_KEYS_MANAGE) {
+        return Err(ApiKeyErrors::Forbidden);
+    }
+
+    let keys = ApiKey::list(&state, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+#[proof_route("DELETE /api-keys/{id}")]
+async fn revoke_api_key(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] _claims: Claims,
+    #[error_override(KeyNotFound)] id: Path<Uuid>,
+) -> Result<HttpResponse, ApiKeyErrors> {
+    if !auth.has_authority(API_KEYS_MANAGE) {
+        return Err(ApiKeyErrors::Forbidden);
+    }
+
+    let key = ApiKey::revoke(&state, id.into_inner(), None).await?;
+    Ok(HttpResponse::Ok().json(key))
+}
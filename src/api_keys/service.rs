@@ -0,0 +1,149 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    api_keys::{
+        CreateApiKeyRequest, CreatedApiKey, entities::ApiKey, errors::api_key::ApiKeyErrors,
+    },
+    helpers::token::{generate_opaque_token, hash_token},
+};
+
+/// Start of every API key, which tells them apart from JWTs in `Authorization` headers.
+pub const API_KEY_PREFIX: &str = "pgmq_";
+/// Header carrying an API key, as an alternative to `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+/// Characters of a key kept in clear as its `prefix`.
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+const API_KEY_COLUMNS: &str = "id, user_id, created_by, name, prefix, permissions, expires_at,
+                               last_used_at, revoked_at, created_at";
+
+impl ApiKey {
+    /// Creates a key for `user_id`, limited to permissions the user currently holds.
+    pub async fn create(
+        state: &AppState,
+        user_id: Uuid,
+        created_by: Uuid,
+        request: &CreateApiKeyRequest,
+    ) -> Result<CreatedApiKey, ApiKeyErrors> {
+        if request
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Err(ApiKeyErrors::ExpiryInPast);
+        }
+
+        let held = Self::owner_permissions(state, user_id)
+            .await?
+            .ok_or(ApiKeyErrors::UserNotFound)?;
+        if !request
+            .permissions
+            .iter()
+            .all(|permission| held.contains(permission))
+        {
+            return Err(ApiKeyErrors::PermissionNotHeld);
+        }
+
+        let key = format!("{API_KEY_PREFIX}{}", generate_opaque_token());
+        let mut permissions = request.permissions.clone();
+        permissions.sort();
+        permissions.dedup();
+
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            "INSERT INTO api_keys
+                 (user_id, created_by, name, prefix, key_hash, permissions, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING {API_KEY_COLUMNS}"
+        ))
+        .bind(user_id)
+        .bind(created_by)
+        .bind(&request.name)
+        .bind(&key[..DISPLAY_PREFIX_LENGTH])
+        .bind(hash_token(&key))
+        .bind(&permissions)
+        .bind(request.expires_at)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| ApiKeyErrors::DatabaseError)?;
+
+        Ok(CreatedApiKey { key, api_key })
+    }
+
+    /// Keys of a user, including revoked and expired ones, newest first.
+    pub async fn list(state: &AppState, user_id: Uuid) -> Result<Vec<ApiKey>, ApiKeyErrors> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC"
+        ))
+        .bind(user_id)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| ApiKeyErrors::DatabaseError)
+    }
+
+    /// Revokes a key, only among the keys of `owner` when given.
+    pub async fn revoke(
+        state: &AppState,
+        id: Uuid,
+        owner: Option<Uuid>,
+    ) -> Result<ApiKey, ApiKeyErrors> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+             WHERE id = $1 AND ($2::UUID IS NULL OR user_id = $2)
+             RETURNING {API_KEY_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(owner)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| ApiKeyErrors::DatabaseError)?
+        .ok_or(ApiKeyErrors::KeyNotFound)
+    }
+
+    /// Finds the active key matching `key` and records its use.
+    ///
+    /// `last_used_at` is written at most once a minute, so busy clients do not turn every
+    /// request into a write.
+    pub async fn authenticate(state: &AppState, key: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            "WITH matched AS (
+                 SELECT {API_KEY_COLUMNS} FROM api_keys
+                 WHERE key_hash = $1
+                   AND revoked_at IS NULL
+                   AND (expires_at IS NULL OR expires_at > NOW())
+             ), touched AS (
+                 UPDATE api_keys SET last_used_at = NOW()
+                 WHERE id IN (SELECT id FROM matched)
+                   AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+             )
+             SELECT * FROM matched"
+        ))
+        .bind(hash_token(key))
+        .fetch_optional(&state.db_pool)
+        .await
+    }
+
+    /// Permissions granted to the roles of a user, `None` for an unknown user.
+    async fn owner_permissions(
+        state: &AppState,
+        user_id: Uuid,
+    ) -> Result<Option<Vec<String>>, ApiKeyErrors> {
+        sqlx::query_scalar!(
+            r#"SELECT COALESCE(
+                   array_agg(DISTINCT rp.permission_name)
+                       FILTER (WHERE rp.permission_name IS NOT NULL),
+                   '{}'
+               ) as "permissions!"
+               FROM users u
+               LEFT JOIN users_role ur ON u.id = ur.user_id
+               LEFT JOIN catalogs.roles r ON ur.role_id = r.id
+               LEFT JOIN catalogs.role_permissions rp ON rp.role_name = r.name
+               WHERE u.id = $1
+               GROUP BY u.id"#,
+            user_id
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| ApiKeyErrors::DatabaseError)
+    }
+}
@@ -0,0 +1,30 @@
+//! Action names recorded in `audit_events`.
+//!
+//! Names are grouped by a dotted prefix, which the audit query accepts to match a whole
+//! group, e.g. `auth.`.
+
+pub const REGISTER: &str = "auth.register";
+pub const LOGIN: &str = "auth.login";
+pub const LOGIN_MFA: &str = "auth.login_mfa";
+pub const LOGOUT: &str = "auth.logout";
+pub const LOGOUT_ALL: &str = "auth.logout_all";
+pub const TOKEN_REFRESH: &str = "auth.token_refresh";
+pub const TOKEN_REJECTED: &str = "auth.token_rejected";
+pub const API_KEY_REJECTED: &str = "auth.api_key_rejected";
+pub const MAGIC_LINK_REQUESTED: &str = "auth.magic_link_requested";
+pub const SESSION_REVOKED: &str = "auth.session_revoked";
+pub const PASSWORD_CHANGED: &str = "password.changed";
+pub const PASSWORD_RESET_REQUESTED: &str = "password.reset_requested";
+pub const PASSWORD_RESET: &str = "password.reset";
+pub const EMAIL_VERIFIED: &str = "email.verified";
+pub const MFA_ENABLED: &str = "mfa.enabled";
+pub const MFA_DISABLED: &str = "mfa.disabled";
+pub const MFA_RECOVERY_CODES_REGENERATED: &str = "mfa.recovery_codes_regenerated";
+pub const USER_CREATED: &str = "user.created";
+pub const USER_UPDATED: &str = "user.updated";
+pub const USER_DEACTIVATED: &str = "user.deactivated";
+pub const USER_ACTIVATED: &str = "user.activated";
+pub const USER_DELETED: &str = "user.deleted";
+pub const ROLE_ASSIGNED: &str = "user.role_assigned";
+pub const ROLE_REMOVED: &str = "user.role_removed";
+pub const ROLE_MFA_CHANGED: &str = "role.mfa_required_changed";
@@ -0,0 +1,2 @@
+mod query;
+pub use query::*;
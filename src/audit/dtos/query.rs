@@ -0,0 +1,31 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::audit::entities::AuditLogEntry;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct AuditEventsQuery {
+    pub actor_id: Option<Uuid>,
+    pub subject_id: Option<Uuid>,
+    /// Exact action, or a prefix ending in `.` matching a group of actions
+    #[validate(length(min = 1, max = 100))]
+    pub action: Option<String>,
+    pub outcome: Option<String>,
+    pub ip: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+    #[validate(range(min = 0))]
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditLogEntry>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
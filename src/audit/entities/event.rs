@@ -0,0 +1,127 @@
+use std::future::{Ready, ready};
+
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, dev::Payload};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    api_keys::entities::ApiKeyScope, middlewares::jwt::Claims, users::entities::DeviceInfo,
+};
+
+pub const OUTCOME_SUCCESS: &str = "success";
+pub const OUTCOME_FAILURE: &str = "failure";
+
+/// An event on its way to the audit log, sent as is to the audit queue.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub occurred_at: DateTime<Utc>,
+    /// User who performed the action, `None` when it is unknown, e.g. a failed login
+    pub actor_id: Option<Uuid>,
+    /// User the action was performed on
+    pub subject_id: Option<Uuid>,
+    pub action: String,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AuditEvent {
+    pub fn success(action: &str, device: &DeviceInfo) -> Self {
+        Self::new(action, OUTCOME_SUCCESS, device)
+    }
+
+    pub fn failure(action: &str, device: &DeviceInfo) -> Self {
+        Self::new(action, OUTCOME_FAILURE, device)
+    }
+
+    fn new(action: &str, outcome: &str, device: &DeviceInfo) -> Self {
+        AuditEvent {
+            occurred_at: Utc::now(),
+            actor_id: None,
+            subject_id: None,
+            action: action.to_string(),
+            outcome: outcome.to_string(),
+            ip: Some(device.ip.clone()),
+            user_agent: device.user_agent.clone(),
+            metadata: serde_json::Map::new(),
+        }
+    }
+
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor_id = Some(user_id);
+        self
+    }
+
+    /// Sets the caller of an authenticated route as the actor, naming the API key it used.
+    pub fn by(mut self, actor: &AuditActor) -> Self {
+        self.actor_id = actor.user_id;
+        match actor.api_key_id {
+            Some(api_key_id) => self.detail("api_key_id", api_key_id.to_string()),
+            None => self,
+        }
+    }
+
+    pub fn subject(mut self, user_id: Uuid) -> Self {
+        self.subject_id = Some(user_id);
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Why the action failed, usually the message of the error returned to the client.
+    pub fn reason(self, reason: impl ToString) -> Self {
+        self.detail("reason", reason.to_string())
+    }
+}
+
+/// Caller of an authenticated route, the user behind its access token or API key.
+///
+/// Both fields are `None` on routes not mounted behind the JWT validator.
+#[derive(Debug, Clone, Default)]
+pub struct AuditActor {
+    pub user_id: Option<Uuid>,
+    pub api_key_id: Option<Uuid>,
+}
+
+impl FromRequest for AuditActor {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let extensions = req.extensions();
+        let actor = match (extensions.get::<Claims>(), extensions.get::<ApiKeyScope>()) {
+            (Some(claims), _) => AuditActor {
+                user_id: Some(claims.user_id),
+                api_key_id: None,
+            },
+            (None, Some(scope)) => AuditActor {
+                user_id: Some(scope.user_id),
+                api_key_id: Some(scope.id),
+            },
+            (None, None) => AuditActor::default(),
+        };
+
+        ready(Ok(actor))
+    }
+}
+
+/// An event stored in `audit_events`.
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub recorded_at: DateTime<Utc>,
+    pub actor_id: Option<Uuid>,
+    pub subject_id: Option<Uuid>,
+    pub action: String,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub metadata: serde_json::Value,
+}
@@ -0,0 +1,21 @@
+use actix_failwrap::ErrorResponse;
+use thiserror::Error;
+
+#[derive(Debug, ErrorResponse, Error)]
+pub enum AuditErrors {
+    #[error("Invalid request")]
+    #[status_code(400)]
+    InvalidRequest,
+
+    #[error("Too many events to export, narrow the filters")]
+    #[status_code(400)]
+    ExportTooLarge,
+
+    #[error("Access denied")]
+    #[status_code(403)]
+    Forbidden,
+
+    #[error("Database error")]
+    #[status_code(500)]
+    DatabaseError,
+}
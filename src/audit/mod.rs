@@ -0,0 +1,20 @@
+pub mod actions;
+
+pub mod entities {
+    mod event;
+    pub use event::*;
+}
+
+mod dtos;
+pub use dtos::*;
+
+pub mod errors;
+
+mod routes;
+pub use routes::admin_config as admin_routes;
+
+mod service;
+pub use service::*;
+
+mod throttle;
+pub use throttle::*;
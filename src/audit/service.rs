@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use sqlx::{Connection, Pool, Postgres, types::Json};
+
+use crate::{
+    AppState,
+    audit::{
+        AuditEventPage, AuditEventsQuery,
+        entities::{AuditEvent, AuditLogEntry, OUTCOME_FAILURE},
+        errors::audit::AuditErrors,
+    },
+};
+
+/// pgmq queue events travel through on their way to `audit_events`.
+pub const AUDIT_QUEUE: &str = "audit_events";
+/// Events moved from the queue to the table per transaction.
+const BATCH_SIZE: i32 = 100;
+/// Seconds a read batch stays hidden from other writers.
+const VISIBILITY_TIMEOUT_SECS: i32 = 30;
+/// Wait before polling an empty queue again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Most events a single CSV export may contain.
+const EXPORT_MAX_ROWS: i64 = 100_000;
+
+const AUDIT_COLUMNS: &str = "id, occurred_at, recorded_at, actor_id, subject_id, action, outcome,
+                             ip, user_agent, metadata";
+
+const AUDIT_FILTERS: &str = "($1::UUID IS NULL OR actor_id = $1)
+               AND ($2::UUID IS NULL OR subject_id = $2)
+               AND ($3::TEXT IS NULL OR action = $3
+                    OR (right($3, 1) = '.' AND starts_with(action, $3)))
+               AND ($4::TEXT IS NULL OR outcome = $4)
+               AND ($5::TEXT IS NULL OR ip = $5)
+               AND ($6::TIMESTAMPTZ IS NULL OR occurred_at >= $6)
+               AND ($7::TIMESTAMPTZ IS NULL OR occurred_at < $7)";
+
+/// Append-only log of authentication and account events.
+///
+/// Events are sent to the [`AUDIT_QUEUE`] from a spawned task, so recording one never
+/// delays the request. Failure events are limited per client IP by [`AuditThrottle`].
+/// [`AuditLog::run_writer`] moves them from the queue to the `audit_events` table, whose
+/// rows cannot be updated or deleted.
+pub struct AuditLog;
+
+impl AuditLog {
+    /// Queues an event without waiting for it, unless it is a failure over the budget of
+    /// its client IP.
+    pub fn record(state: &AppState, event: AuditEvent) {
+        if event.outcome == OUTCOME_FAILURE
+            && event
+                .ip
+                .as_deref()
+                .is_some_and(|ip| !state.audit_throttle.allow(ip))
+        {
+            return;
+        }
+
+        let pool = state.db_pool.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = sqlx::query("SELECT pgmq.send($1, $2)")
+                .bind(AUDIT_QUEUE)
+                .bind(Json(&event))
+                .execute(&pool)
+                .await
+            {
+                eprintln!("Failed to queue audit event {}: {e}", event.action);
+            }
+        });
+    }
+
+    pub async fn create_queue(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pgmq.create($1)")
+            .bind(AUDIT_QUEUE)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Moves queued events into `audit_events`, polling until the process exits.
+    pub async fn run_writer(pool: Pool<Postgres>) {
+        loop {
+            match Self::write_batch(&pool).await {
+                Ok(written) if written == BATCH_SIZE as usize => continue,
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to write audit events: {e}"),
+            }
+            actix_web::rt::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Inserts a batch of queued events and deletes their messages in one transaction,
+    /// so every event is written once even when the writer stops halfway.
+    ///
+    /// Messages that are not events, and events the table refuses, are archived instead of
+    /// blocking the queue. Each event is inserted under a savepoint so that a refused one
+    /// does not roll back the rest of the batch.
+    async fn write_batch(pool: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let messages = sqlx::query_as::<_, (i64, serde_json::Value)>(
+            "SELECT msg_id, message FROM pgmq.read($1, $2, $3)",
+        )
+        .bind(AUDIT_QUEUE)
+        .bind(VISIBILITY_TIMEOUT_SECS)
+        .bind(BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut written = Vec::with_capacity(messages.len());
+        let mut rejected = Vec::new();
+        for (msg_id, message) in &messages {
+            let Ok(event) = serde_json::from_value::<AuditEvent>(message.clone()) else {
+                eprintln!("Archiving malformed audit event message {msg_id}");
+                rejected.push(*msg_id);
+                continue;
+            };
+
+            let mut savepoint = (*tx).begin().await?;
+            let inserted = sqlx::query(
+                "INSERT INTO audit_events
+                     (occurred_at, actor_id, subject_id, action, outcome, ip, user_agent,
+                      metadata)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(event.occurred_at)
+            .bind(event.actor_id)
+            .bind(event.subject_id)
+            .bind(&event.action)
+            .bind(&event.outcome)
+            .bind(&event.ip)
+            .bind(&event.user_agent)
+            .bind(Json(&event.metadata))
+            .execute(&mut *savepoint)
+            .await;
+
+            match inserted {
+                Ok(_) => {
+                    savepoint.commit().await?;
+                    written.push(*msg_id);
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    eprintln!("Archiving audit event message {msg_id} refused by the table: {e}");
+                    rejected.push(*msg_id);
+                }
+            }
+        }
+
+        sqlx::query("SELECT pgmq.delete($1, $2::BIGINT[])")
+            .bind(AUDIT_QUEUE)
+            .bind(&written)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("SELECT pgmq.archive($1, $2::BIGINT[])")
+            .bind(AUDIT_QUEUE)
+            .bind(&rejected)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(messages.len())
+    }
+
+    /// Events matching the filters, newest first.
+    pub async fn search(
+        state: &AppState,
+        query: &AuditEventsQuery,
+    ) -> Result<AuditEventPage, AuditErrors> {
+        let limit = query.limit.unwrap_or(50).clamp(1, 500);
+        let offset = query.offset.unwrap_or(0).max(0);
+        let total = Self::count(state, query).await?;
+
+        let events = sqlx::query_as::<_, AuditLogEntry>(&format!(
+            "SELECT {AUDIT_COLUMNS} FROM audit_events
+             WHERE {AUDIT_FILTERS}
+             ORDER BY occurred_at DESC, id
+             LIMIT $8 OFFSET $9"
+        ))
+        .bind(query.actor_id)
+        .bind(query.subject_id)
+        .bind(&query.action)
+        .bind(&query.outcome)
+        .bind(&query.ip)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuditErrors::DatabaseError)?;
+
+        Ok(AuditEventPage {
+            events,
+            total,
+            limit,
+            offset,
+        })
+    }
+
+    /// Every event matching the filters as CSV, oldest first. `limit` and `offset` are
+    /// ignored, a selection over `EXPORT_MAX_ROWS` events is refused.
+    pub async fn export_csv(
+        state: &AppState,
+        query: &AuditEventsQuery,
+    ) -> Result<String, AuditErrors> {
+        if Self::count(state, query).await? > EXPORT_MAX_ROWS {
+            return Err(AuditErrors::ExportTooLarge);
+        }
+
+        let events = sqlx::query_as::<_, AuditLogEntry>(&format!(
+            "SELECT {AUDIT_COLUMNS} FROM audit_events
+             WHERE {AUDIT_FILTERS}
+             ORDER BY occurred_at, id"
+        ))
+        .bind(query.actor_id)
+        .bind(query.subject_id)
+        .bind(&query.action)
+        .bind(&query.outcome)
+        .bind(&query.ip)
+        .bind(query.from)
+        .bind(query.to)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuditErrors::DatabaseError)?;
+
+        let mut csv = String::from(
+            "id,occurred_at,recorded_at,actor_id,subject_id,action,outcome,ip,user_agent,metadata\r\n",
+        );
+        for event in events {
+            let fields = [
+                event.id.to_string(),
+                event.occurred_at.to_rfc3339(),
+                event.recorded_at.to_rfc3339(),
+                event.actor_id.map(|id| id.to_string()).unwrap_or_default(),
+                event
+                    .subject_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                event.action,
+                event.outcome,
+                event.ip.unwrap_or_default(),
+                event.user_agent.unwrap_or_default(),
+                event.metadata.to_string(),
+            ];
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push_str("\r\n");
+        }
+
+        Ok(csv)
+    }
+
+    async fn count(state: &AppState, query: &AuditEventsQuery) -> Result<i64, AuditErrors> {
+        sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM audit_events WHERE {AUDIT_FILTERS}"
+        ))
+        .bind(query.actor_id)
+        .bind(query.subject_id)
+        .bind(&query.action)
+        .bind(&query.outcome)
+        .bind(&query.ip)
+        .bind(query.from)
+        .bind(query.to)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| AuditErrors::DatabaseError)
+    }
+}
+
+/// Quotes a CSV field when needed. Fields that spreadsheets would read as a formula,
+/// such as a crafted user agent, are prefixed with `'`.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_keeps_plain_values() {
+        assert_eq!(csv_field("auth.login"), "auth.login");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_field_quotes_separators_and_quotes() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn csv_field_neutralizes_formulas() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("\tcmd"), "'\tcmd");
+        assert_eq!(csv_field("\rcmd"), "\"'\rcmd\"");
+    }
+}
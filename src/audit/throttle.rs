@@ -0,0 +1,59 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::Utc;
+
+/// Failure events recorded per client IP within a window.
+const FAILURES_PER_IP: u32 = 60;
+const WINDOW_SECONDS: i64 = 60;
+/// Client IPs tracked before expired windows are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Per client IP budget of failure events.
+///
+/// Failures such as rejected tokens or API keys can be produced by anyone without
+/// credentials, so past the budget of an IP they are dropped instead of written. Success
+/// events need a valid login or credentials and are always recorded.
+#[derive(Debug, Default)]
+pub struct AuditThrottle {
+    /// End of the current window and failures counted in it, per client IP
+    counters: Mutex<HashMap<String, (i64, u32)>>,
+}
+
+impl AuditThrottle {
+    /// Counts a failure event from `ip`, `false` when the IP has used up its budget.
+    pub fn allow(&self, ip: &str) -> bool {
+        let now = Utc::now().timestamp();
+        let window_end = now - now.rem_euclid(WINDOW_SECONDS) + WINDOW_SECONDS;
+
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        if counters.len() > PRUNE_THRESHOLD {
+            counters.retain(|_, (end, _)| *end > now);
+        }
+
+        let counter = counters.entry(ip.to_string()).or_insert((window_end, 0));
+        if counter.0 != window_end {
+            *counter = (window_end, 0);
+        }
+        counter.1 = counter.1.saturating_add(1);
+
+        if counter.1 == FAILURES_PER_IP + 1 {
+            eprintln!("Dropping audit failure events from {ip} until the window ends");
+        }
+        counter.1 <= FAILURES_PER_IP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_failures_up_to_the_budget_per_ip() {
+        let throttle = AuditThrottle::default();
+        for _ in 0..FAILURES_PER_IP {
+            assert!(throttle.allow("203.0.113.7"));
+        }
+        assert!(!throttle.allow("203.0.113.7"));
+        assert!(throttle.allow("198.51.100.1"));
+    }
+}
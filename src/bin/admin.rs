@@ -0,0 +1,333 @@
+use std::{io::IsTerminal, sync::Arc};
+
+use backend::{
+    AppState,
+    audit::AuditThrottle,
+    config::Config,
+    helpers::validate_password::validate_password,
+    middlewares::revocation::RevocationCache,
+    oidc::ProviderCache,
+    queues::QueueService,
+    users::{
+        AuthUser,
+        entities::{PartialUser, Role},
+        errors::auth::AuthErrors,
+    },
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use sqlx::Pool;
+
+/// Operational commands for queues, users and roles.
+#[derive(Debug, Parser)]
+#[command(name = "admin")]
+struct Cli {
+    /// Output format
+    #[arg(long, value_enum, global = true, default_value_t = Format::Table)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect and operate pgmq queues
+    #[command(subcommand)]
+    Queues(QueuesCommand),
+
+    /// Manage user accounts
+    #[command(subcommand)]
+    Users(UsersCommand),
+
+    /// Inspect and configure the role catalog
+    #[command(subcommand)]
+    Roles(RolesCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum QueuesCommand {
+    /// List every queue
+    List,
+
+    /// Show metrics and the oldest messages of a queue
+    Show {
+        queue: String,
+        #[arg(long, default_value_t = 10)]
+        limit: i64,
+    },
+
+    /// Move messages from `<queue>_dlq` back into `<queue>`
+    RequeueDlq {
+        queue: String,
+        /// Dead letter message ids to requeue, every message when omitted
+        #[arg(long = "msg-id")]
+        msg_ids: Vec<i64>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum UsersCommand {
+    /// Create a user holding the admin role, prompting for the password or reading it
+    /// from stdin when it is not a terminal
+    CreateAdmin {
+        #[arg(long)]
+        email: String,
+    },
+
+    /// Assign a role from `catalogs.roles` to a user
+    AssignRole {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        role: String,
+    },
+
+    /// Remove a role from a user
+    RemoveRole {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        role: String,
+    },
+
+    /// Invalidate every token issued to a user
+    RevokeTokens {
+        #[arg(long)]
+        email: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum RolesCommand {
+    /// List every role
+    List,
+
+    /// Require two-factor authentication from holders of a role
+    RequireMfa {
+        #[arg(long)]
+        role: String,
+        /// Stop requiring it instead
+        #[arg(long)]
+        disable: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct ActionResult {
+    message: String,
+}
+
+#[actix_web::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let db_pool = Pool::<sqlx::Postgres>::connect(&Config::from_env().database_url)
+        .await
+        .expect("Failed to connect to the database");
+    let state = AppState {
+        db_pool,
+        revocations: Arc::new(RevocationCache::default()),
+        oidc: Arc::new(ProviderCache::default()),
+        audit_throttle: Arc::new(AuditThrottle::default()),
+    };
+
+    if let Err(e) = run(&state, cli.command, cli.format).await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(
+    state: &AppState,
+    command: Command,
+    format: Format,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Queues(QueuesCommand::List) => {
+            let queues = QueueService::list_queues(state).await?;
+            render(
+                format,
+                &queues,
+                &["QUEUE", "PARTITIONED", "UNLOGGED", "CREATED AT"],
+                |q| {
+                    vec![
+                        q.queue_name.clone(),
+                        q.is_partitioned.to_string(),
+                        q.is_unlogged.to_string(),
+                        q.created_at.to_rfc3339(),
+                    ]
+                },
+            )
+        }
+        Command::Queues(QueuesCommand::Show { queue, limit }) => {
+            let metrics = QueueService::metrics(state, &queue).await?;
+            let messages = QueueService::peek(state, &queue, limit).await?;
+            match format {
+                Format::Json => print_json(&serde_json::json!({
+                    "metrics": metrics,
+                    "messages": messages,
+                })),
+                Format::Table => {
+                    print_table(
+                        &["QUEUE", "LENGTH", "TOTAL", "OLDEST (s)", "NEWEST (s)"],
+                        &[vec![
+                            metrics.queue_name,
+                            metrics.queue_length.to_string(),
+                            metrics.total_messages.to_string(),
+                            optional(metrics.oldest_msg_age_sec),
+                            optional(metrics.newest_msg_age_sec),
+                        ]],
+                    );
+                    println!();
+                    render(
+                        format,
+                        &messages,
+                        &["ID", "READS", "ENQUEUED AT", "MESSAGE"],
+                        |m| {
+                            vec![
+                                m.msg_id.to_string(),
+                                m.read_ct.to_string(),
+                                m.enqueued_at.to_rfc3339(),
+                                m.message.to_string(),
+                            ]
+                        },
+                    )
+                }
+            }
+        }
+        Command::Queues(QueuesCommand::RequeueDlq { queue, msg_ids }) => {
+            let requeued = QueueService::requeue_dead_letters(state, &queue, &msg_ids).await?;
+            print_message(
+                format,
+                format!("Requeued {} message(s) into {queue}", requeued.len()),
+            )
+        }
+        Command::Users(UsersCommand::CreateAdmin { email }) => {
+            let password = read_password()?;
+            validate_password(&password, &[&email]).map_err(AuthErrors::WeakPassword)?;
+            let auth_user = AuthUser { email, password };
+            let user_id = PartialUser::create_user_with_role(state, &auth_user, "admin").await?;
+            PartialUser::mark_email_verified(state, user_id).await?;
+            print_message(format, format!("Created admin user {user_id}"))
+        }
+        Command::Users(UsersCommand::AssignRole { email, role }) => {
+            let user = PartialUser::find_by_email(state, &email).await?;
+            PartialUser::assign_role(state, user.id, &role).await?;
+            print_message(format, format!("Assigned role {role} to {email}"))
+        }
+        Command::Users(UsersCommand::RemoveRole { email, role }) => {
+            let user = PartialUser::find_by_email(state, &email).await?;
+            PartialUser::remove_role(state, user.id, &role).await?;
+            print_message(format, format!("Removed role {role} from {email}"))
+        }
+        Command::Users(UsersCommand::RevokeTokens { email }) => {
+            let user = PartialUser::find_by_email(state, &email).await?;
+            PartialUser::revoke_tokens(state, user.id).await?;
+            print_message(format, format!("Revoked tokens of {email}"))
+        }
+        Command::Roles(RolesCommand::List) => {
+            let roles = Role::list(state).await?;
+            render(format, &roles, &["ID", "NAME", "MFA REQUIRED"], |r| {
+                vec![r.id.to_string(), r.name.clone(), r.mfa_required.to_string()]
+            })
+        }
+        Command::Roles(RolesCommand::RequireMfa { role, disable }) => {
+            Role::set_mfa_required(state, &role, !disable).await?;
+            let message = if disable {
+                format!("Role {role} no longer requires two-factor authentication")
+            } else {
+                format!("Role {role} now requires two-factor authentication")
+            };
+            print_message(format, message)
+        }
+    }
+}
+
+/// Reads a password without echoing it, or the first line of stdin when piped, so it never
+/// shows up in the process list or shell history.
+fn read_password() -> Result<String, Box<dyn std::error::Error>> {
+    let password = if std::io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if rpassword::prompt_password("Confirm password: ")? != password {
+            return Err("Passwords do not match".into());
+        }
+        password
+    } else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    Ok(password)
+}
+
+fn render<T: Serialize>(
+    format: Format,
+    items: &[T],
+    headers: &[&str],
+    row: impl Fn(&T) -> Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Json => print_json(&items),
+        Format::Table => {
+            let rows: Vec<Vec<String>> = items.iter().map(row).collect();
+            print_table(headers, &rows);
+            Ok(())
+        }
+    }
+}
+
+fn print_message(format: Format, message: String) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Json => print_json(&ActionResult { message }),
+        Format::Table => {
+            println!("{message}");
+            Ok(())
+        }
+    }
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    println!("{}", line(headers.to_vec()).trim_end());
+    for row in rows {
+        println!(
+            "{}",
+            line(row.iter().map(String::as_str).collect()).trim_end()
+        );
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
@@ -0,0 +1,75 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+
+static CONFIGURED: OnceLock<Option<BreachedPasswords>> = OnceLock::new();
+
+/// Local list of breached passwords, indexed like the Have I Been Pwned range API.
+///
+/// Passwords are looked up by the first five hex characters of their SHA-1 hash and then
+/// by the remaining suffix, so the list is only ever searched by hash range.
+#[derive(Debug, Default)]
+pub struct BreachedPasswords {
+    ranges: HashMap<String, HashSet<String>>,
+}
+
+impl BreachedPasswords {
+    /// The list from `BREACHED_PASSWORDS_FILE`, read on first use (the server loads it on
+    /// startup). `None` when unset.
+    ///
+    /// Lines holding a SHA-1 hash (optionally followed by `:count`, as in the Have I Been
+    /// Pwned downloads) are taken as is, any other line is a plaintext password.
+    pub fn configured() -> Option<&'static BreachedPasswords> {
+        CONFIGURED
+            .get_or_init(|| {
+                let path = Config::from_env().breached_passwords_file?;
+                let contents = std::fs::read_to_string(&path)
+                    .expect("BREACHED_PASSWORDS_FILE must be a readable file");
+                Some(Self::parse(&contents))
+            })
+            .as_ref()
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut list = BreachedPasswords::default();
+        for line in contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let hash = line.split(':').next().unwrap_or_default();
+            let hash = if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                hash.to_ascii_uppercase()
+            } else {
+                sha1_hex(line)
+            };
+            let (prefix, suffix) = hash.split_at(5);
+            list.ranges
+                .entry(prefix.to_string())
+                .or_default()
+                .insert(suffix.to_string());
+        }
+        list
+    }
+
+    /// Hash suffixes of every breached password whose hash starts with `prefix`.
+    pub fn range(&self, prefix: &str) -> Option<&HashSet<String>> {
+        self.ranges.get(prefix)
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = sha1_hex(password);
+        let (prefix, suffix) = hash.split_at(5);
+        self.range(prefix)
+            .is_some_and(|suffixes| suffixes.contains(suffix))
+    }
+}
+
+fn sha1_hex(value: &str) -> String {
+    hex::encode_upper(Sha1::digest(value.as_bytes()))
+}
@@ -0,0 +1,107 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{HttpRequest, http::header::HeaderName};
+
+use crate::config::Config;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// IP address of the client that sent the request.
+///
+/// `X-Forwarded-For` is only honoured with `TRUST_PROXY_HEADERS=true`, otherwise any client
+/// could pick the address it is tracked under. Even then only the entry appended by the
+/// outermost trusted proxy is used, `TRUSTED_PROXY_HOPS` from the right, as the entries
+/// before it come from the client. The peer address is used when that entry is missing or
+/// is not an IP address.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let config = Config::from_env();
+    let forwarded = config
+        .trust_proxy_headers
+        .then(|| forwarded_ip(req, config.trusted_proxy_hops))
+        .flatten();
+
+    forwarded
+        .or_else(|| req.peer_addr().map(|address| address.ip()))
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Entry of `X-Forwarded-For` appended `hops` proxies away, the last one being 1.
+fn forwarded_ip(req: &HttpRequest, hops: usize) -> Option<IpAddr> {
+    // Repeated headers form a single list, in order
+    let entries: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    let entry = entries
+        .len()
+        .checked_sub(hops)
+        .map(|index| entries[index])?;
+    entry
+        .parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn request(forwarded_for: &[&str]) -> HttpRequest {
+        forwarded_for
+            .iter()
+            .fold(TestRequest::default(), |req, value| {
+                req.append_header((X_FORWARDED_FOR, *value))
+            })
+            .to_http_request()
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn uses_the_entry_appended_by_the_trusted_proxy() {
+        let req = request(&["1.1.1.1, 203.0.113.7"]);
+        assert_eq!(forwarded_ip(&req, 1), ip("203.0.113.7"));
+        assert_eq!(forwarded_ip(&req, 2), ip("1.1.1.1"));
+        assert_eq!(forwarded_ip(&req, 3), None);
+    }
+
+    #[test]
+    fn joins_repeated_headers() {
+        let req = request(&["1.1.1.1", "203.0.113.7, 198.51.100.1"]);
+        assert_eq!(forwarded_ip(&req, 1), ip("198.51.100.1"));
+        assert_eq!(forwarded_ip(&req, 3), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn accepts_ports_and_ipv6() {
+        assert_eq!(
+            forwarded_ip(&request(&["203.0.113.7:5123"]), 1),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_ip(&request(&["2001:db8::1"]), 1),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            forwarded_ip(&request(&["[2001:db8::1]:443"]), 1),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn rejects_entries_that_are_not_addresses() {
+        let long = "a".repeat(100);
+        assert_eq!(forwarded_ip(&request(&[&long]), 1), None);
+        assert_eq!(forwarded_ip(&request(&["203.0.113.7, unknown"]), 1), None);
+        assert_eq!(forwarded_ip(&request(&[]), 1), None);
+    }
+}
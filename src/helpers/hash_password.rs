@@ -1,10 +1,203 @@
-use bcrypt::{DEFAULT_COST, hash, verify};
+use actix_web::web;
+use argon2::{
+    Argon2, Params, PasswordHash, PasswordHasher as _, PasswordVerifier as _,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use thiserror::Error;
 
-pub fn hash_password(password: String) -> Result<String, bcrypt::BcryptError> {
-    let hashed_password = hash(password, DEFAULT_COST)?;
-    Ok(hashed_password)
+use crate::config::Config;
+
+#[derive(Debug, Error)]
+pub enum HashPasswordError {
+    #[error("bcrypt error: {0}")]
+    Bcrypt(#[from] bcrypt::BcryptError),
+
+    #[error("argon2 error: {0}")]
+    Argon2(argon2::password_hash::Error),
+
+    #[error("invalid argon2 parameters: {0}")]
+    Argon2Params(argon2::Error),
+
+    #[error("password hashing task was cancelled")]
+    Blocking,
+}
+
+/// Algorithm used for new password hashes, set by `PASSWORD_HASHER`.
+///
+/// Stored hashes are verified with the algorithm they name, so switching hashers (or
+/// their parameters) keeps existing passwords working until they are rehashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHasher {
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+}
+
+impl PasswordHasher {
+    pub fn configured() -> Self {
+        let config = Config::from_env();
+        match config.password_hasher.as_str() {
+            "argon2id" => PasswordHasher::Argon2id {
+                memory_kib: config.argon2_memory_kib,
+                iterations: config.argon2_iterations,
+                parallelism: config.argon2_parallelism,
+            },
+            "bcrypt" => PasswordHasher::Bcrypt {
+                cost: config.bcrypt_cost,
+            },
+            _ => panic!("PASSWORD_HASHER must be argon2id or bcrypt"),
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, HashPasswordError> {
+        match *self {
+            PasswordHasher::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(memory_kib, iterations, parallelism, None)
+                    .map_err(HashPasswordError::Argon2Params)?;
+                let salt = SaltString::generate(&mut OsRng);
+                Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(HashPasswordError::Argon2)
+            }
+            PasswordHasher::Bcrypt { cost } => Ok(bcrypt::hash(password, cost)?),
+        }
+    }
+
+    /// Whether a stored hash was made with another algorithm or other parameters.
+    pub fn needs_rehash(&self, hashed: &str) -> bool {
+        match *self {
+            PasswordHasher::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let Ok(parsed) = PasswordHash::new(hashed) else {
+                    return true;
+                };
+                let Ok(params) = Params::try_from(&parsed) else {
+                    return true;
+                };
+                parsed.algorithm != argon2::Algorithm::Argon2id.ident()
+                    || params.m_cost() != memory_kib
+                    || params.t_cost() != iterations
+                    || params.p_cost() != parallelism
+            }
+            PasswordHasher::Bcrypt { cost } => bcrypt_cost(hashed) != Some(cost),
+        }
+    }
+}
+
+/// Checks a password against a bcrypt or argon2 hash.
+fn verify(password: &str, hashed: &str) -> bool {
+    if hashed.starts_with("$argon2") {
+        return PasswordHash::new(hashed)
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+    }
+
+    bcrypt::verify(password, hashed).unwrap_or(false)
 }
 
-pub fn verify_password(password: String, hashed_db_password: String) -> bool {
-    verify(password, &hashed_db_password).unwrap_or(false)
+/// Cost of a `$2b$12$...` style bcrypt hash.
+fn bcrypt_cost(hashed: &str) -> Option<u32> {
+    if !hashed.starts_with("$2") {
+        return None;
+    }
+    hashed.split('$').nth(2)?.parse().ok()
+}
+
+/// Hashes a password with the configured hasher, on the blocking thread pool.
+pub async fn hash_password(password: String) -> Result<String, HashPasswordError> {
+    web::block(move || PasswordHasher::configured().hash(&password))
+        .await
+        .map_err(|_| HashPasswordError::Blocking)?
+}
+
+/// Verifies a password on the blocking thread pool, `false` on any error.
+pub async fn verify_password(password: String, hashed_db_password: String) -> bool {
+    web::block(move || verify(&password, &hashed_db_password))
+        .await
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so hashing stays fast in tests
+    const ARGON2: PasswordHasher = PasswordHasher::Argon2id {
+        memory_kib: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+    const BCRYPT: PasswordHasher = PasswordHasher::Bcrypt { cost: 4 };
+
+    #[test]
+    fn fresh_hashes_need_no_rehash() {
+        for hasher in [ARGON2, BCRYPT] {
+            let hashed = hasher.hash("password").unwrap();
+            assert!(!hasher.needs_rehash(&hashed));
+        }
+    }
+
+    #[test]
+    fn other_algorithms_need_rehash() {
+        assert!(ARGON2.needs_rehash(&BCRYPT.hash("password").unwrap()));
+        assert!(BCRYPT.needs_rehash(&ARGON2.hash("password").unwrap()));
+    }
+
+    #[test]
+    fn other_parameters_need_rehash() {
+        let hashed = ARGON2.hash("password").unwrap();
+        for hasher in [
+            PasswordHasher::Argon2id {
+                memory_kib: 16,
+                iterations: 1,
+                parallelism: 1,
+            },
+            PasswordHasher::Argon2id {
+                memory_kib: 8,
+                iterations: 2,
+                parallelism: 1,
+            },
+            PasswordHasher::Argon2id {
+                memory_kib: 8,
+                iterations: 1,
+                parallelism: 2,
+            },
+        ] {
+            assert!(hasher.needs_rehash(&hashed));
+        }
+
+        let hashed = BCRYPT.hash("password").unwrap();
+        assert!(PasswordHasher::Bcrypt { cost: 5 }.needs_rehash(&hashed));
+    }
+
+    #[test]
+    fn argon2i_and_unparseable_hashes_need_rehash() {
+        let params = Params::new(8, 1, 1, None).unwrap();
+        let salt = SaltString::generate(&mut OsRng);
+        let argon2i = Argon2::new(argon2::Algorithm::Argon2i, argon2::Version::V0x13, params)
+            .hash_password(b"password", &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(ARGON2.needs_rehash(&argon2i));
+        assert!(ARGON2.needs_rehash("not a hash"));
+        assert!(BCRYPT.needs_rehash("not a hash"));
+    }
 }
@@ -1,2 +1,5 @@
+pub mod breached_passwords;
+pub mod client_ip;
 pub mod hash_password;
+pub mod token;
 pub mod validate_password;
@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Generates a random opaque token, suitable for refresh or one-time tokens.
+pub fn generate_opaque_token() -> String {
+    format!("{}{}", Uuid::new_v4(), Uuid::new_v4()).replace("-", "")
+}
+
+/// Hex encoded SHA-256 of a token, the only form in which opaque tokens are stored.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -1,14 +1,276 @@
-use regex::Regex;
+use std::fmt;
 
-pub fn is_valid_password(password: &str) -> bool {
-    if password.len() < 8 {
-        return false;
+use crate::{config::Config, helpers::breached_passwords::BreachedPasswords};
+
+/// Shortest email local part or username checked against passwords.
+const MIN_IDENTIFIER_LENGTH: usize = 3;
+
+/// A password policy rule a password failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordRule {
+    MinLength(usize),
+    MaxLength(usize),
+    Uppercase,
+    Lowercase,
+    Digit,
+    Special,
+    ContainsIdentifier,
+    Breached,
+}
+
+impl fmt::Display for PasswordRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordRule::MinLength(length) => {
+                write!(f, "must be at least {length} characters long")
+            }
+            PasswordRule::MaxLength(length) => {
+                write!(f, "must be at most {length} characters long")
+            }
+            PasswordRule::Uppercase => write!(f, "must contain an uppercase letter"),
+            PasswordRule::Lowercase => write!(f, "must contain a lowercase letter"),
+            PasswordRule::Digit => write!(f, "must contain a digit"),
+            PasswordRule::Special => write!(f, "must contain a special character"),
+            PasswordRule::ContainsIdentifier => {
+                write!(f, "must not contain your email or username")
+            }
+            PasswordRule::Breached => write!(f, "appears in a list of breached passwords"),
+        }
+    }
+}
+
+/// Every rule a password failed, in policy order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordViolations(pub Vec<PasswordRule>);
+
+impl fmt::Display for PasswordViolations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", rules.join("; "))
+    }
+}
+
+/// Requirements for new passwords, set by the `PASSWORD_*` variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    /// Reject passwords containing the email (or its local part) or the username
+    pub forbid_identifiers: bool,
+}
+
+impl PasswordPolicy {
+    pub fn configured() -> Self {
+        let config = Config::from_env();
+        PasswordPolicy {
+            min_length: config.password_min_length,
+            max_length: config.password_max_length,
+            require_uppercase: config.password_require_uppercase,
+            require_lowercase: config.password_require_lowercase,
+            require_digit: config.password_require_digit,
+            require_special: config.password_require_special,
+            forbid_identifiers: config.password_forbid_identifiers,
+        }
+    }
+
+    /// Checks a password against the policy and, when configured, the breached list.
+    ///
+    /// `identifiers` are the email and username of the account, when known.
+    pub fn check(&self, password: &str, identifiers: &[&str]) -> Result<(), PasswordViolations> {
+        self.check_against(password, identifiers, BreachedPasswords::configured())
+    }
+
+    /// Like [`check`](Self::check), with `breached` instead of the configured list.
+    pub fn check_against(
+        &self,
+        password: &str,
+        identifiers: &[&str],
+        breached: Option<&BreachedPasswords>,
+    ) -> Result<(), PasswordViolations> {
+        let mut failed = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            failed.push(PasswordRule::MinLength(self.min_length));
+        }
+        if length > self.max_length {
+            failed.push(PasswordRule::MaxLength(self.max_length));
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            failed.push(PasswordRule::Uppercase);
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            failed.push(PasswordRule::Lowercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            failed.push(PasswordRule::Digit);
+        }
+        if self.require_special
+            && !password
+                .chars()
+                .any(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        {
+            failed.push(PasswordRule::Special);
+        }
+        if self.forbid_identifiers && contains_identifier(password, identifiers) {
+            failed.push(PasswordRule::ContainsIdentifier);
+        }
+        if breached.is_some_and(|list| list.contains(password)) {
+            failed.push(PasswordRule::Breached);
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordViolations(failed))
+        }
+    }
+}
+
+/// Checks a new password against the configured policy.
+pub fn validate_password(password: &str, identifiers: &[&str]) -> Result<(), PasswordViolations> {
+    PasswordPolicy::configured().check(password, identifiers)
+}
+
+fn contains_identifier(password: &str, identifiers: &[&str]) -> bool {
+    let password = password.to_lowercase();
+    identifiers
+        .iter()
+        .flat_map(|identifier| {
+            // Emails are also checked by their local part
+            let local_part = identifier.split_once('@').map(|(local, _)| local);
+            [Some(*identifier), local_part]
+        })
+        .flatten()
+        .filter(|identifier| identifier.chars().count() >= MIN_IDENTIFIER_LENGTH)
+        .any(|identifier| password.contains(&identifier.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+
+    fn strict_policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_special: true,
+            forbid_identifiers: true,
+        }
+    }
+
+    #[test]
+    fn accepts_password_meeting_every_rule() {
+        let policy = strict_policy();
+        assert_eq!(
+            policy.check_against("Str0ng!Pass", &["jane@example.com"], None),
+            Ok(())
+        );
     }
 
-    let has_uppercase = Regex::new(r"[A-Z]").unwrap().is_match(password);
-    let has_special = Regex::new(r"[!@#$%^&*(),.?|<>]")
-        .unwrap()
-        .is_match(password);
+    #[test]
+    fn lists_every_failed_rule() {
+        let policy = strict_policy();
+        assert_eq!(
+            policy.check_against("   ", &[], None),
+            Err(PasswordViolations(vec![
+                PasswordRule::MinLength(8),
+                PasswordRule::Uppercase,
+                PasswordRule::Lowercase,
+                PasswordRule::Digit,
+                PasswordRule::Special,
+            ]))
+        );
+        assert_eq!(
+            policy.check_against("abcdefghijklmnopq", &[], None),
+            Err(PasswordViolations(vec![
+                PasswordRule::MaxLength(16),
+                PasswordRule::Uppercase,
+                PasswordRule::Digit,
+                PasswordRule::Special,
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_passwords_containing_identifiers() {
+        let policy = strict_policy();
+        let violation = Err(PasswordViolations(vec![PasswordRule::ContainsIdentifier]));
+
+        assert_eq!(
+            policy.check_against("Jane@Example.com1", &["jane@example.com"], None),
+            Err(PasswordViolations(vec![
+                PasswordRule::MaxLength(16),
+                PasswordRule::ContainsIdentifier,
+            ]))
+        );
+        // The local part of an email is enough
+        assert_eq!(
+            policy.check_against("x1!JANEx", &["jane@example.com"], None),
+            violation
+        );
+        assert_eq!(
+            policy.check_against("Qwerty1!admin", &["jane@example.com", "admin"], None),
+            violation
+        );
+        // Identifiers shorter than the minimum are ignored
+        assert_eq!(
+            policy.check_against("Str0ng!Jo", &["jo@example.com"], None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn allows_identifiers_when_not_forbidden() {
+        let policy = PasswordPolicy {
+            forbid_identifiers: false,
+            ..strict_policy()
+        };
+        assert_eq!(
+            policy.check_against("x1!JANEx", &["jane@example.com"], None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_breached_passwords() {
+        let policy = strict_policy();
+        let breached = BreachedPasswords::parse("Summer2024!\n\n");
+        // Have I Been Pwned `HASH:count` line, hashes are matched in any case
+        let hibp = BreachedPasswords::parse(&format!(
+            "{}:12",
+            hex::encode(Sha1::digest("P@ssw0rd!".as_bytes()))
+        ));
 
-    has_uppercase && has_special
+        assert_eq!(
+            policy.check_against("Summer2024!", &[], Some(&breached)),
+            Err(PasswordViolations(vec![PasswordRule::Breached]))
+        );
+        assert_eq!(
+            policy.check_against("P@ssw0rd!", &[], Some(&hibp)),
+            Err(PasswordViolations(vec![PasswordRule::Breached]))
+        );
+        assert_eq!(
+            policy.check_against("Str0ng!Pass", &[], Some(&breached)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn formats_violations_in_order() {
+        let violations = PasswordViolations(vec![PasswordRule::MinLength(8), PasswordRule::Digit]);
+        assert_eq!(
+            violations.to_string(),
+            "must be at least 8 characters long; must contain a digit"
+        );
+    }
 }
@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use sqlx::Pool;
+
+use crate::{audit::AuditThrottle, middlewares::revocation::RevocationCache, oidc::ProviderCache};
+
+pub mod api_keys;
+pub mod audit;
+pub mod config;
+pub mod errors;
+pub mod helpers;
+pub mod mailer;
+pub mod middlewares;
+pub mod oidc;
+pub mod queues;
+pub mod users;
+pub mod workflows;
+
+pub struct AppState {
+    pub db_pool: Pool<sqlx::Postgres>,
+    pub revocations: Arc<RevocationCache>,
+    pub oidc: Arc<ProviderCache>,
+    pub audit_throttle: Arc<AuditThrottle>,
+}
@@ -1,2 +1,6 @@
+mod magic_link;
 mod reset_password;
+mod verification;
+pub use magic_link::*;
 pub use reset_password::*;
+pub use verification::*;
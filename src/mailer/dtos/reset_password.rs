@@ -12,7 +12,6 @@ pub struct ForgotPasswordRequest {
 pub struct ResetPasswordRequest {
     #[validate(length(min = 32, max = 255))]
     pub token: String,
-    #[validate(length(min = 8))]
     pub new_password: String,
 }
 
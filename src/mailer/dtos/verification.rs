@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 32, max = 255))]
+    pub token: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct ResendVerificationRequest {
+    #[validate(email)]
+    #[validate(length(min = 5, max = 100))]
+    pub email: String,
+}
@@ -1,6 +1,4 @@
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmailTemplate {
@@ -8,23 +6,3 @@ pub struct EmailTemplate {
     pub subject: String,
     pub body: String,
 }
-
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct PasswordResetToken {
-    pub id: Uuid,
-    pub user_id: Uuid,
-    pub token: String,
-    pub expires_at: chrono::DateTime<chrono::Utc>,
-    pub used: bool,
-    pub created_at: chrono::DateTime<chrono::Utc>,
-}
-
-impl PasswordResetToken {
-    pub fn is_expired(&self) -> bool {
-        chrono::Utc::now() > self.expires_at
-    }
-
-    pub fn is_valid(&self) -> bool {
-        !self.used && !self.is_expired()
-    }
-}
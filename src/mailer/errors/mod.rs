@@ -2,6 +2,8 @@ use actix_web::{HttpResponse, ResponseError};
 use serde_json::json;
 use thiserror::Error;
 
+use crate::helpers::validate_password::PasswordViolations;
+
 #[allow(dead_code)]
 #[derive(Debug, Error)]
 pub enum MailerErrors {
@@ -31,6 +33,12 @@ pub enum MailerErrors {
 
     #[error("Password hash error")]
     PasswordHashError,
+
+    #[error("Password does not meet the policy: {0}")]
+    WeakPassword(PasswordViolations),
+
+    #[error("Magic link login is disabled")]
+    MagicLinkDisabled,
 }
 
 impl ResponseError for MailerErrors {
@@ -47,6 +55,8 @@ impl ResponseError for MailerErrors {
             MailerErrors::DatabaseError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             MailerErrors::UserNotFound => actix_web::http::StatusCode::NOT_FOUND,
             MailerErrors::PasswordHashError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            MailerErrors::WeakPassword(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            MailerErrors::MagicLinkDisabled => actix_web::http::StatusCode::NOT_FOUND,
         };
 
         HttpResponse::build(status_code).json(json!({
@@ -5,57 +5,86 @@ use uuid::Uuid;
 
 use crate::{
     AppState,
+    audit::{AuditLog, actions, entities::AuditEvent},
     config::Config,
-    helpers::hash_password::hash_password,
-    mailer::{
-        entities::{EmailTemplate, PasswordResetToken},
-        errors::MailerErrors,
+    helpers::{
+        hash_password::hash_password,
+        token::{generate_opaque_token, hash_token},
+        validate_password::validate_password,
     },
-    users::entities::PartialUser,
+    mailer::{entities::EmailTemplate, errors::MailerErrors},
+    users::entities::{DeviceInfo, PartialUser},
 };
 
 pub struct MailerService;
 
 impl MailerService {
+    /// Emails a password reset link, invalidating links sent before.
+    ///
+    /// The response is the same whether or not the email is registered, and the email is
+    /// sent in the background so response times do not tell either.
     pub async fn send_password_reset_email(
         state: &AppState,
         email: &str,
+        device: &DeviceInfo,
     ) -> Result<String, MailerErrors> {
+        let message = "If an account exists for this email, a password reset link has been sent";
+
         let user = sqlx::query_as::<_, PartialUser>(
-            "SELECT id, email, password_hash FROM users WHERE email = $1",
+            "SELECT id, email, username, password_hash FROM users WHERE email = $1",
         )
         .bind(email)
         .fetch_optional(&state.db_pool)
         .await
         .map_err(|_| MailerErrors::DatabaseError)?;
 
-        let user = user.ok_or(MailerErrors::UserNotFound)?;
+        let Some(user) = user else {
+            AuditLog::record(
+                state,
+                AuditEvent::failure(actions::PASSWORD_RESET_REQUESTED, device)
+                    .detail("email", email)
+                    .reason("No account with this email"),
+            );
+            return Ok(message.to_string());
+        };
 
-        let token = Self::generate_reset_token();
+        let token = generate_opaque_token();
         let expires_at = chrono::Utc::now() + chrono::Duration::hours(1);
 
-        let _token_record = sqlx::query!(
-            "INSERT INTO password_reset_tokens (id, user_id, token, expires_at, used, created_at) 
-             VALUES ($1, $2, $3, $4, $5, $6) 
-             RETURNING id",
-            Uuid::new_v4(),
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "UPDATE password_reset_tokens SET used = true WHERE user_id = $1 AND used = false",
+            user.id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+             VALUES ($1, $2, $3)",
             user.id,
-            token,
-            expires_at,
-            false,
-            chrono::Utc::now()
+            hash_token(&token),
+            expires_at
         )
-        .fetch_one(&state.db_pool)
+        .execute(&mut *tx)
         .await
         .map_err(|_| MailerErrors::DatabaseError)?;
 
+        tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
+
         let reset_url = format!(
             "{}/reset-password?token={}",
             Config::from_env().frontend_url,
             token
         );
         let email_template = EmailTemplate {
-            to: email.to_string(),
+            to: user.email,
             subject: "Password Reset Request".to_string(),
             body: format!(
                 "Hello,\n\nYou have requested to reset your password. Please click the link below to reset your password:\n\n{}\n\nThis link will expire in 1 hour.\n\nIf you did not request this, please ignore this email.\n\nBest regards,\nPGMQ Team",
@@ -63,38 +92,319 @@ impl MailerService {
             ),
         };
 
-        Self::send_email(&email_template).await?;
+        AuditLog::record(
+            state,
+            AuditEvent::success(actions::PASSWORD_RESET_REQUESTED, device)
+                .subject(user.id)
+                .detail("email", email),
+        );
+        actix_web::rt::spawn(async move {
+            if let Err(e) = Self::send_email(&email_template).await {
+                eprintln!("Failed to send password reset email: {e}");
+            }
+        });
 
-        Ok("Password reset email sent successfully".to_string())
+        Ok(message.to_string())
     }
 
+    /// Sets a new password with a reset token.
+    ///
+    /// The token is consumed by the same statement that checks it, so concurrent requests
+    /// with one token cannot both succeed. Every other reset token and session of the user
+    /// is invalidated.
     pub async fn reset_password(
         state: &AppState,
         token: &str,
         new_password: &str,
+        device: &DeviceInfo,
     ) -> Result<String, MailerErrors> {
-        let token_record = sqlx::query_as::<_, PasswordResetToken>(
-            "SELECT id, user_id, token, expires_at, used, created_at 
-             FROM password_reset_tokens 
-             WHERE token = $1",
+        let token_hash = hash_token(token);
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        let user_id = sqlx::query_scalar!(
+            "UPDATE password_reset_tokens SET used = true
+             WHERE token_hash = $1 AND used = false AND expires_at > NOW()
+             RETURNING user_id",
+            token_hash
         )
-        .bind(token)
-        .fetch_optional(&state.db_pool)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|_| MailerErrors::DatabaseError)?;
 
-        let token_record = token_record.ok_or(MailerErrors::TokenNotFoundOrExpired)?;
+        let Some(user_id) = user_id else {
+            let used = sqlx::query_scalar!(
+                r#"SELECT used as "used!" FROM password_reset_tokens WHERE token_hash = $1"#,
+                token_hash
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+            let error = match used {
+                Some(true) => MailerErrors::TokenAlreadyUsed,
+                _ => MailerErrors::TokenNotFoundOrExpired,
+            };
+            AuditLog::record(
+                state,
+                AuditEvent::failure(actions::PASSWORD_RESET, device).reason(&error),
+            );
+            return Err(error);
+        };
+
+        let user = sqlx::query!("SELECT email, username FROM users WHERE id = $1", user_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?
+            .ok_or(MailerErrors::UserNotFound)?;
+        // Returning before the commit leaves the token unused
+        if let Err(violations) = validate_password(new_password, &[&user.email, &user.username]) {
+            let error = MailerErrors::WeakPassword(violations);
+            AuditLog::record(
+                state,
+                AuditEvent::failure(actions::PASSWORD_RESET, device)
+                    .subject(user_id)
+                    .reason(&error),
+            );
+            return Err(error);
+        }
 
-        if !token_record.is_valid() {
-            if token_record.used {
-                return Err(MailerErrors::TokenAlreadyUsed);
-            } else {
-                return Err(MailerErrors::TokenNotFoundOrExpired);
+        let hashed_password = hash_password(new_password.to_string())
+            .await
+            .map_err(|_| MailerErrors::PasswordHashError)?;
+
+        sqlx::query!(
+            "UPDATE users SET password_hash = $1 WHERE id = $2",
+            hashed_password,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "UPDATE password_reset_tokens SET used = true WHERE user_id = $1 AND used = false",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
+
+        AuditLog::record(
+            state,
+            AuditEvent::success(actions::PASSWORD_RESET, device).subject(user_id),
+        );
+        PartialUser::revoke_tokens(state, user_id)
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        Ok("Password reset successfully".to_string())
+    }
+
+    /// Emails a one-time sign-in link, invalidating links sent before.
+    ///
+    /// Like password reset, the response does not tell whether the email is registered.
+    /// Deactivated users get no link.
+    pub async fn send_magic_link_email(
+        state: &AppState,
+        email: &str,
+        device: &DeviceInfo,
+    ) -> Result<String, MailerErrors> {
+        let config = Config::from_env();
+        if !config.magic_link_enabled {
+            return Err(MailerErrors::MagicLinkDisabled);
+        }
+        let message = "If an account exists for this email, a sign-in link has been sent";
+
+        let user_id =
+            sqlx::query_scalar!("SELECT id FROM users WHERE email = $1 AND is_active", email)
+                .fetch_optional(&state.db_pool)
+                .await
+                .map_err(|_| MailerErrors::DatabaseError)?;
+
+        let Some(user_id) = user_id else {
+            AuditLog::record(
+                state,
+                AuditEvent::failure(actions::MAGIC_LINK_REQUESTED, device)
+                    .detail("email", email)
+                    .reason("No active account with this email"),
+            );
+            return Ok(message.to_string());
+        };
+
+        let token = generate_opaque_token();
+        let expires_at =
+            chrono::Utc::now() + chrono::Duration::minutes(config.magic_link_ttl_minutes);
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "UPDATE magic_link_tokens SET used = true WHERE user_id = $1 AND used = false",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "INSERT INTO magic_link_tokens (user_id, token_hash, expires_at)
+             VALUES ($1, $2, $3)",
+            user_id,
+            hash_token(&token),
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
+
+        let login_url = format!("{}/magic-link?token={}", config.frontend_url, token);
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "Your sign-in link".to_string(),
+            body: format!(
+                "Hello,\n\nClick the link below to sign in:\n\n{}\n\nThis link can be used once and expires in {} minutes.\n\nIf you did not request it, you can ignore this email.\n\nBest regards,\nPGMQ Team",
+                login_url, config.magic_link_ttl_minutes
+            ),
+        };
+
+        AuditLog::record(
+            state,
+            AuditEvent::success(actions::MAGIC_LINK_REQUESTED, device)
+                .subject(user_id)
+                .detail("email", email),
+        );
+        actix_web::rt::spawn(async move {
+            if let Err(e) = Self::send_email(&email_template).await {
+                eprintln!("Failed to send magic link email: {e}");
             }
+        });
+
+        Ok(message.to_string())
+    }
+
+    /// Consumes a sign-in token and returns the email of its user.
+    ///
+    /// Following the link proves the user owns the address, so it is marked verified.
+    pub async fn consume_magic_link(state: &AppState, token: &str) -> Result<String, MailerErrors> {
+        if !Config::from_env().magic_link_enabled {
+            return Err(MailerErrors::MagicLinkDisabled);
         }
 
-        let hashed_password =
-            hash_password(new_password.to_string()).map_err(|_| MailerErrors::PasswordHashError)?;
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        let user_id = sqlx::query_scalar!(
+            "UPDATE magic_link_tokens SET used = true
+             WHERE token_hash = $1 AND used = false AND expires_at > NOW()
+             RETURNING user_id",
+            hash_token(token)
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?
+        .ok_or(MailerErrors::TokenNotFoundOrExpired)?;
+
+        let email = sqlx::query_scalar!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW())
+             WHERE id = $1
+             RETURNING email",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
+
+        Ok(email)
+    }
+
+    pub async fn send_account_locked_email(
+        email: &str,
+        locked_for_seconds: i64,
+    ) -> Result<(), MailerErrors> {
+        let minutes = (locked_for_seconds + 59) / 60;
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "Your account was temporarily locked".to_string(),
+            body: format!(
+                "Hello,\n\nAfter several failed login attempts your account was locked for {} minute(s).\n\nIf these attempts were not yours, we recommend changing your password once the lock expires.\n\nBest regards,\nPGMQ Team",
+                minutes
+            ),
+        };
+
+        Self::send_email(&email_template).await
+    }
+
+    /// Welcomes a user whose account was created for them, pointing to the reset page to
+    /// choose a password.
+    pub async fn send_welcome_email(email: &str) -> Result<(), MailerErrors> {
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "Welcome to PGMQ".to_string(),
+            body: format!(
+                "Hello,\n\nAn account was created for you with this email address.\n\nTo choose your password, request a reset link at:\n\n{}/forgot-password\n\nBest regards,\nPGMQ Team",
+                Config::from_env().frontend_url
+            ),
+        };
+
+        Self::send_email(&email_template).await
+    }
+
+    pub async fn send_password_changed_email(email: &str) -> Result<(), MailerErrors> {
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "Your password was changed".to_string(),
+            body: "Hello,\n\nThe password of your account was just changed and every other session was signed out.\n\nIf you did not make this change, please reset your password immediately and contact support.\n\nBest regards,\nPGMQ Team".to_string(),
+        };
+
+        Self::send_email(&email_template).await
+    }
+
+    /// Tells the user about a login from a device their account was not used on before.
+    pub async fn send_new_device_email(
+        email: &str,
+        device: &DeviceInfo,
+    ) -> Result<(), MailerErrors> {
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "New sign-in to your account".to_string(),
+            body: format!(
+                "Hello,\n\nYour account was just signed in to from a new device.\n\nDevice: {}\nIP address: {}\nTime: {}\n\nIf this was not you, sign that session out from your account's session list and change your password.\n\nBest regards,\nPGMQ Team",
+                device.user_agent.as_deref().unwrap_or("Unknown"),
+                device.ip,
+                chrono::Utc::now().format("%Y-%m-%d %H:%M UTC")
+            ),
+        };
+
+        Self::send_email(&email_template).await
+    }
+
+    /// Emails a link confirming the user's email address, invalidating links sent before.
+    ///
+    /// The email is sent in the background, a failure to send it is only logged.
+    pub async fn send_verification_email(
+        state: &AppState,
+        user_id: Uuid,
+        email: &str,
+    ) -> Result<(), MailerErrors> {
+        let token = generate_opaque_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(24);
 
         let mut tx = state
             .db_pool
@@ -103,17 +413,19 @@ impl MailerService {
             .map_err(|_| MailerErrors::DatabaseError)?;
 
         sqlx::query!(
-            "UPDATE users SET password_hash = $1 WHERE id = $2",
-            hashed_password,
-            token_record.user_id
+            "UPDATE email_verification_tokens SET used = true WHERE user_id = $1 AND used = false",
+            user_id
         )
         .execute(&mut *tx)
         .await
         .map_err(|_| MailerErrors::DatabaseError)?;
 
         sqlx::query!(
-            "UPDATE password_reset_tokens SET used = true WHERE id = $1",
-            token_record.id
+            "INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+             VALUES ($1, $2, $3)",
+            user_id,
+            hash_token(&token),
+            expires_at
         )
         .execute(&mut *tx)
         .await
@@ -121,7 +433,125 @@ impl MailerService {
 
         tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
 
-        Ok("Password reset successfully".to_string())
+        let verify_url = format!(
+            "{}/verify-email?token={}",
+            Config::from_env().frontend_url,
+            token
+        );
+        let email_template = EmailTemplate {
+            to: email.to_string(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Hello,\n\nPlease confirm your email address by clicking the link below:\n\n{}\n\nThis link will expire in 24 hours.\n\nIf you did not create an account, please ignore this email.\n\nBest regards,\nPGMQ Team",
+                verify_url
+            ),
+        };
+
+        actix_web::rt::spawn(async move {
+            if let Err(e) = Self::send_email(&email_template).await {
+                eprintln!("Failed to send verification email: {e}");
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Sends a new verification link to an unverified address.
+    ///
+    /// The response is the same whether the email is unknown, unverified or verified
+    /// already, so it does not tell which addresses are registered.
+    pub async fn resend_verification_email(
+        state: &AppState,
+        email: &str,
+    ) -> Result<String, MailerErrors> {
+        let message =
+            "If this email belongs to an unverified account, a verification link has been sent";
+
+        let user = sqlx::query!(
+            "SELECT id, email_verified_at FROM users WHERE email = $1",
+            email
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        if let Some(user) = user
+            && user.email_verified_at.is_none()
+        {
+            Self::send_verification_email(state, user.id, email).await?;
+        }
+
+        Ok(message.to_string())
+    }
+
+    /// Marks an email address verified with a verification token.
+    ///
+    /// The token is consumed by the same statement that checks it, so it cannot be used
+    /// twice by concurrent requests.
+    pub async fn verify_email(
+        state: &AppState,
+        token: &str,
+        device: &DeviceInfo,
+    ) -> Result<String, MailerErrors> {
+        let token_hash = hash_token(token);
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+        let user_id = sqlx::query_scalar!(
+            "UPDATE email_verification_tokens SET used = true
+             WHERE token_hash = $1 AND used = false AND expires_at > NOW()
+             RETURNING user_id",
+            token_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        let Some(user_id) = user_id else {
+            let token = sqlx::query!(
+                r#"SELECT user_id, used as "used!" FROM email_verification_tokens
+                   WHERE token_hash = $1"#,
+                token_hash
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|_| MailerErrors::DatabaseError)?;
+
+            let mut failure = AuditEvent::failure(actions::EMAIL_VERIFIED, device);
+            let error = match token {
+                Some(token) => {
+                    failure = failure.subject(token.user_id);
+                    if token.used {
+                        MailerErrors::TokenAlreadyUsed
+                    } else {
+                        MailerErrors::TokenNotFoundOrExpired
+                    }
+                }
+                None => MailerErrors::TokenNotFoundOrExpired,
+            };
+            AuditLog::record(state, failure.reason(&error));
+            return Err(error);
+        };
+
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| MailerErrors::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| MailerErrors::DatabaseError)?;
+
+        AuditLog::record(
+            state,
+            AuditEvent::success(actions::EMAIL_VERIFIED, device).subject(user_id),
+        );
+        Ok("Email verified successfully".to_string())
     }
 
     async fn send_email(template: &EmailTemplate) -> Result<(), MailerErrors> {
@@ -150,15 +580,12 @@ impl MailerService {
             .timeout(Some(Duration::from_secs(10)))
             .build();
 
-        mailer
-            .send(&email)
+        // The SMTP transport is blocking, keep it off the async workers
+        actix_web::rt::task::spawn_blocking(move || mailer.send(&email))
+            .await
+            .map_err(|_| MailerErrors::EmailSendError)?
             .map_err(|_| MailerErrors::EmailSendError)?;
 
         Ok(())
     }
-
-    fn generate_reset_token() -> String {
-        use uuid::Uuid;
-        format!("{}{}", Uuid::new_v4(), Uuid::new_v4()).replace("-", "")
-    }
 }
@@ -1,23 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use actix_cors::Cors;
-use actix_web::{App, HttpServer, web::Data};
+use actix_web::{
+    App, HttpServer,
+    http::Method,
+    web::{self, Data},
+};
+use actix_web_httpauth::middleware::HttpAuthentication;
 use actix_web_prometheus::PrometheusMetricsBuilder;
+use backend::{
+    AppState, api_keys,
+    audit::{self, AuditLog, AuditThrottle},
+    config::Config,
+    helpers::{breached_passwords::BreachedPasswords, hash_password::PasswordHasher},
+    middlewares::{
+        jwt::validator,
+        rate_limit::{RateLimitKey, RateLimitStore, RateLimiter},
+        revocation::RevocationCache,
+        signing_keys::SigningKeys,
+    },
+    oidc::{self, ProviderCache},
+    queues::BlobStore,
+    users::{self, entities::EmailVerificationPolicy},
+    workflows::{self, WorkflowService},
+};
 use sqlx::Pool;
 
-use crate::config::Config;
-
-mod config;
-mod errors;
-mod helpers;
-mod mailer;
-mod middlewares;
-mod users;
-
-pub struct AppState {
-    pub db_pool: Pool<sqlx::Postgres>,
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
@@ -60,15 +68,131 @@ async fn main() -> std::io::Result<()> {
         }
     }
 
+    // Fails fast on invalid settings instead of on the first request reading them
+    SigningKeys::configured();
+    EmailVerificationPolicy::configured();
+    PasswordHasher::configured();
+    BlobStore::configured();
+    // Reads the breached password list now rather than during the first password check
+    BreachedPasswords::configured();
+
+    AuditLog::create_queue(&client)
+        .await
+        .expect("Failed to create the audit queue");
+    actix_web::rt::spawn(AuditLog::run_writer(client.clone()));
+
+    let revocations = Arc::new(RevocationCache::default());
+    let oidc_providers = Arc::new(ProviderCache::default());
+    let audit_throttle = Arc::new(AuditThrottle::default());
+
+    actix_web::rt::spawn(WorkflowService::run_worker(AppState {
+        db_pool: client.clone(),
+        revocations: revocations.clone(),
+        oidc: oidc_providers.clone(),
+        audit_throttle: audit_throttle.clone(),
+    }));
+
+    // Windows are in seconds, routes sending email are also limited per address
+    let rate_limiter = RateLimiter::new(RateLimitStore::configured(&client))
+        .rule(Method::POST, "/login", 30, 60, RateLimitKey::Ip)
+        .rule(Method::POST, "/login/mfa", 30, 60, RateLimitKey::Ip)
+        .rule(
+            Method::POST,
+            "/login/magic-link",
+            20,
+            3600,
+            RateLimitKey::Ip,
+        )
+        .rule(
+            Method::POST,
+            "/login/magic-link",
+            3,
+            3600,
+            RateLimitKey::Email,
+        )
+        .rule(
+            Method::POST,
+            "/login/magic-link/verify",
+            20,
+            3600,
+            RateLimitKey::Ip,
+        )
+        .rule(
+            Method::GET,
+            "/oidc/{provider}/authorize",
+            30,
+            60,
+            RateLimitKey::Ip,
+        )
+        .rule(
+            Method::POST,
+            "/oidc/{provider}/callback",
+            30,
+            60,
+            RateLimitKey::Ip,
+        )
+        .rule(Method::POST, "/register", 10, 3600, RateLimitKey::Ip)
+        .rule(Method::POST, "/forgot-password", 20, 3600, RateLimitKey::Ip)
+        .rule(
+            Method::POST,
+            "/forgot-password",
+            3,
+            3600,
+            RateLimitKey::Email,
+        )
+        .rule(Method::POST, "/reset-password", 20, 3600, RateLimitKey::Ip)
+        .rule(Method::POST, "/verify-email", 20, 3600, RateLimitKey::Ip)
+        .rule(
+            Method::POST,
+            "/resend-verification",
+            20,
+            3600,
+            RateLimitKey::Ip,
+        )
+        .rule(
+            Method::POST,
+            "/resend-verification",
+            3,
+            3600,
+            RateLimitKey::Email,
+        )
+        .rule(
+            Method::POST,
+            "/api/me/password",
+            10,
+            3600,
+            RateLimitKey::UserId,
+        );
+
     HttpServer::new(move || {
         let cors = Cors::permissive();
         App::new()
             .app_data(Data::new(AppState {
                 db_pool: client.clone(),
+                revocations: revocations.clone(),
+                oidc: oidc_providers.clone(),
+                audit_throttle: audit_throttle.clone(),
             }))
+            .wrap(rate_limiter.clone())
             .wrap(cors)
             .wrap(prometheus.clone())
             .configure(users::routes)
+            .configure(oidc::routes)
+            .service(
+                web::scope("/api")
+                    .wrap(HttpAuthentication::with_fn(validator))
+                    .configure(users::api_routes)
+                    .configure(oidc::api_routes)
+                    .configure(api_keys::api_routes),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(HttpAuthentication::with_fn(validator))
+                    .configure(users::admin_routes)
+                    .configure(api_keys::admin_routes)
+                    .configure(audit::admin_routes)
+                    .configure(workflows::routes),
+            )
     })
     .bind("127.0.0.1:8080")?
     .run()
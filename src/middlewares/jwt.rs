@@ -1,32 +1,81 @@
 use crate::AppState;
-use crate::config::Config;
+use crate::api_keys::entities::{ApiKey, ApiKeyScope};
+use crate::api_keys::{API_KEY_HEADER, API_KEY_PREFIX};
+use crate::audit::{AuditLog, actions, entities::AuditEvent};
+use crate::middlewares::signing_keys::SigningKeys;
+use crate::users::entities::{DeviceInfo, EmailVerificationPolicy, Session};
+use std::future::{Ready, ready};
+
 use actix_web::web::Data;
-use actix_web::{Error, dev::ServiceRequest, error};
+use actix_web::{
+    Error, FromRequest, HttpMessage, HttpRequest,
+    dev::{Payload, ServiceRequest},
+    error,
+};
 use actix_web_grants::authorities::AttachAuthorities;
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::encode;
 use serde::{Deserialize, Serialize};
 use sqlx::Error as SqlxError;
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct TokenStruct {
-    pub token: String,
+pub struct AuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    /// Lifetime of the access token in seconds
+    pub expires_in: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Claims {
     pub iss: String,
     pub exp: usize,
     pub iat: usize,
+    pub jti: Uuid,
     pub token_type: String,
     pub user_id: Uuid,
-    pub user_role: String,
+    /// Names of every role held by the user when the token was issued
+    pub roles: Vec<String>,
+    /// Session the token was issued for, absent on tokens that belong to no login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
 }
 
-pub fn get_secret_key() -> String {
-    Config::from_env().secret_key
+impl Claims {
+    /// Issue time in milliseconds, read from the UUIDv7 `jti`.
+    ///
+    /// `iat` only has second precision, which is too coarse to tell tokens issued right
+    /// before a revocation from the ones issued right after it.
+    pub fn issued_at_millis(&self) -> i64 {
+        self.jti
+            .get_timestamp()
+            .map(|timestamp| {
+                let (seconds, nanos) = timestamp.to_unix();
+                seconds as i64 * 1000 + i64::from(nanos / 1_000_000)
+            })
+            .unwrap_or(self.iat as i64 * 1000)
+    }
+}
+
+/// Claims of the token that authenticated the request.
+///
+/// Only available to handlers mounted behind [`validator`], which stores the decoded
+/// claims in the request extensions.
+impl FromRequest for Claims {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Claims>()
+                .cloned()
+                .ok_or_else(|| error::ErrorUnauthorized("Token not specified")),
+        )
+    }
 }
 
 pub fn generate_token(
@@ -34,45 +83,40 @@ pub fn generate_token(
     duration_minutes: i64,
     token_type: String,
     user_id: Uuid,
-    user_role: String,
+    roles: Vec<String>,
+    session_id: Option<Uuid>,
 ) -> String {
-    let header = Header::new(Algorithm::HS512);
-    let encoding_key = EncodingKey::from_secret(get_secret_key().as_bytes());
+    let keys = SigningKeys::configured();
     let exp = (Utc::now() + Duration::minutes(duration_minutes)).timestamp() as usize;
     let iat = Utc::now().timestamp() as usize;
     let my_claims = Claims {
         iss,
         exp,
         iat,
+        jti: Uuid::now_v7(),
         token_type,
         user_id,
-        user_role,
+        roles,
+        sid: session_id,
     };
-    encode(&header, &my_claims, &encoding_key).unwrap()
+    encode(&keys.header(), &my_claims, keys.encoding_key()).unwrap()
 }
 
-#[allow(dead_code)]
 pub fn validate_token(token: String) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let validation = Validation::new(Algorithm::HS512);
-    let decoding_key = DecodingKey::from_secret(get_secret_key().as_bytes());
-    let result = decode::<Claims>(&token, &decoding_key, &validation);
-    match result {
-        Ok(c) => Ok(c.claims),
-        Err(e) => Err(e),
-    }
+    SigningKeys::configured().verify::<Claims>(&token)
 }
 
-#[allow(dead_code)]
+/// Authenticates `/api` and `/admin` requests with a user access token or an API key.
+///
+/// API keys are sent as `X-Api-Key` or as a bearer token starting with `pgmq_`. They attach
+/// the permissions of the key that the owner still holds, and an [`ApiKeyScope`] instead of
+/// [`Claims`], so routes acting on the caller's own account keep requiring a user token.
 pub async fn validator(
     req: ServiceRequest,
     credenciales: Option<BearerAuth>,
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let Some(credenciales) = credenciales else {
-        return Err((error::ErrorBadRequest("Token not specified"), req));
-    };
-    let token = credenciales.token();
     let state = match req.app_data::<Data<AppState>>() {
-        Some(data) => data,
+        Some(data) => data.clone(),
         None => {
             return Err((
                 error::ErrorInternalServerError("Could not get application state."),
@@ -80,36 +124,183 @@ pub async fn validator(
             ));
         }
     };
-    match validate_token(token.to_owned()) {
+
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            credenciales
+                .as_ref()
+                .map(|credenciales| credenciales.token())
+                .filter(|token| token.starts_with(API_KEY_PREFIX))
+        })
+        .map(str::to_owned);
+    if let Some(api_key) = api_key {
+        return api_key_validator(req, &state, &api_key).await;
+    }
+
+    let Some(credenciales) = credenciales else {
+        return Err((error::ErrorBadRequest("Token not specified"), req));
+    };
+    let rejected = |req: &ServiceRequest, reason: &str, user_id: Option<Uuid>| {
+        record_rejection(&state, req, actions::TOKEN_REJECTED, reason, user_id)
+    };
+    match validate_token(credenciales.token().to_owned()) {
+        Ok(token) if token.token_type != "access" => {
+            rejected(&req, "Invalid token type", Some(token.user_id));
+            Err((error::ErrorUnauthorized("Invalid token type."), req))
+        }
         Ok(token) => {
-            match sqlx::query!(
-                "SELECT r.name as role_name FROM users u 
-                 JOIN users_role ur ON u.id = ur.user_id 
-                 JOIN catalogs.roles r ON ur.role_id = r.id 
-                 WHERE u.id = $1",
-                token.user_id
-            )
-            .fetch_one(&state.db_pool)
-            .await
+            if state
+                .revocations
+                .sync_if_stale(&state.db_pool)
+                .await
+                .is_err()
             {
-                Ok(record) => {
-                    let role = record.role_name;
-                    match role.as_str() {
-                        "admin" => req.attach(vec!["admin".to_string()]),
-                        "user" => req.attach(vec!["user".to_string()]),
-                        "store_manager" => req.attach(vec!["store_manager".to_string()]),
-                        "store_admin" => req.attach(vec!["store_admin".to_string()]),
-                        _ => req.attach(vec!["user".to_string()]), // default role
-                    }
-                    Ok(req)
-                }
-                Err(SqlxError::RowNotFound) => Err((error::ErrorNotFound("User not found."), req)),
-                Err(_) => Err((
+                return Err((
                     error::ErrorInternalServerError("Database query error."),
                     req,
-                )),
+                ));
+            }
+            if state.revocations.is_revoked(&token) {
+                rejected(&req, "Token revoked", Some(token.user_id));
+                return Err((error::ErrorUnauthorized("Token revoked."), req));
+            }
+
+            let req = attach_account(req, &state, token.user_id, None)
+                .await
+                .inspect_err(|(e, req)| rejected(req, &e.to_string(), Some(token.user_id)))?;
+            if let Some(session_id) = token.sid
+                && let Err(e) = Session::touch(&state, session_id).await
+            {
+                eprintln!("Failed to record session activity: {e}");
+            }
+            req.extensions_mut().insert(token);
+            Ok(req)
+        }
+        Err(e) => {
+            rejected(&req, &e.to_string(), None);
+            Err((error::ErrorForbidden("Access denied."), req))
+        }
+    }
+}
+
+async fn api_key_validator(
+    req: ServiceRequest,
+    state: &AppState,
+    api_key: &str,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let rejected = |req: &ServiceRequest, reason: &str, user_id: Option<Uuid>| {
+        record_rejection(state, req, actions::API_KEY_REJECTED, reason, user_id)
+    };
+    let key = match ApiKey::authenticate(state, api_key).await {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            rejected(&req, "Invalid, expired or revoked API key", None);
+            return Err((error::ErrorUnauthorized("Invalid API key."), req));
+        }
+        Err(_) => {
+            return Err((
+                error::ErrorInternalServerError("Database query error."),
+                req,
+            ));
+        }
+    };
+
+    let req = attach_account(req, state, key.user_id, Some(&key.permissions))
+        .await
+        .inspect_err(|(e, req)| rejected(req, &e.to_string(), Some(key.user_id)))?;
+    req.extensions_mut().insert(ApiKeyScope {
+        id: key.id,
+        user_id: key.user_id,
+    });
+    Ok(req)
+}
+
+/// Records a request refused by the validator, `user_id` being the account the
+/// credentials belong to when known.
+fn record_rejection(
+    state: &AppState,
+    req: &ServiceRequest,
+    action: &str,
+    reason: &str,
+    user_id: Option<Uuid>,
+) {
+    let mut event = AuditEvent::failure(action, &DeviceInfo::of(req.request()))
+        .detail("path", req.path())
+        .reason(reason);
+    if let Some(user_id) = user_id {
+        event = event.subject(user_id);
+    }
+    AuditLog::record(state, event);
+}
+
+/// Checks the account of the caller and attaches its permissions as authorities.
+///
+/// `scope` narrows the permissions down to the ones granted to an API key.
+async fn attach_account(
+    req: ServiceRequest,
+    state: &AppState,
+    user_id: Uuid,
+    scope: Option<&[String]>,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    match sqlx::query!(
+        r#"SELECT u.is_active,
+                u.email_verified_at IS NOT NULL as "email_verified!",
+                u.mfa_enabled_at IS NOT NULL as "mfa_enabled!",
+                COALESCE(bool_or(r.mfa_required), FALSE) as "mfa_required!",
+                COALESCE(
+                    array_agg(DISTINCT r.name) FILTER (WHERE r.name IS NOT NULL),
+                    '{}'
+                ) as "roles!",
+                COALESCE(
+                    array_agg(DISTINCT rp.permission_name)
+                        FILTER (WHERE rp.permission_name IS NOT NULL),
+                    '{}'
+                ) as "permissions!"
+         FROM users u
+         LEFT JOIN users_role ur ON u.id = ur.user_id
+         LEFT JOIN catalogs.roles r ON ur.role_id = r.id
+         LEFT JOIN catalogs.role_permissions rp ON rp.role_name = r.name
+         WHERE u.id = $1
+         GROUP BY u.id"#,
+        user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    {
+        Ok(record) if !record.is_active => {
+            Err((error::ErrorForbidden("Account deactivated."), req))
+        }
+        Ok(record) => {
+            let policy = EmailVerificationPolicy::configured();
+            if !record.email_verified && policy == EmailVerificationPolicy::Required {
+                return Err((error::ErrorForbidden("Email address not verified."), req));
+            }
+
+            let unverified =
+                !record.email_verified && policy == EmailVerificationPolicy::Restricted;
+            if unverified || (record.mfa_required && !record.mfa_enabled) {
+                // Only routes that need no permission, like MFA enrollment, are reachable
+                // until the email is verified and MFA set up where a role requires it
+                req.attach(Vec::<String>::new());
+            } else if let Some(scope) = scope {
+                let mut authorities = record.permissions;
+                authorities.retain(|permission| scope.contains(permission));
+                req.attach(authorities);
+            } else {
+                // Role names are kept next to their permissions so role checks keep working
+                let mut authorities = record.permissions;
+                authorities.extend(record.roles);
+                req.attach(authorities);
             }
+            Ok(req)
         }
-        Err(_) => Err((error::ErrorForbidden("Access denied."), req)),
+        Err(SqlxError::RowNotFound) => Err((error::ErrorNotFound("User not found."), req)),
+        Err(_) => Err((
+            error::ErrorInternalServerError("Database query error."),
+            req,
+        )),
     }
 }
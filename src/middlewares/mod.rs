@@ -1 +1,4 @@
 pub mod jwt;
+pub mod rate_limit;
+pub mod revocation;
+pub mod signing_keys;
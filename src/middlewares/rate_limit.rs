@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{
+    Error, HttpResponse,
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::{
+        Method,
+        header::{HeaderName, HeaderValue, RETRY_AFTER},
+    },
+    web::Bytes,
+};
+use chrono::Utc;
+use sqlx::{Pool, Postgres};
+
+use crate::{config::Config, helpers::client_ip::client_ip, middlewares::jwt::validate_token};
+
+/// Entries kept by the in-memory store before expired windows are pruned.
+const MEMORY_STORE_PRUNE_THRESHOLD: usize = 10_000;
+
+/// What a limit is counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Client IP, see [`client_ip`]
+    Ip,
+    /// `user_id` of a valid bearer token, the client IP without one
+    UserId,
+    /// `email` field of the JSON body, the client IP without one
+    Email,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitRule {
+    pub method: Method,
+    pub path: &'static str,
+    pub limit: u32,
+    pub window_seconds: i64,
+    pub key: RateLimitKey,
+}
+
+/// Where request counters live.
+///
+/// `Memory` is only correct with a single instance, `Postgres` shares the counters through
+/// the `rate_limits` table.
+#[derive(Debug, Clone)]
+pub enum RateLimitStore {
+    Memory(Arc<Mutex<HashMap<String, (i64, u32)>>>),
+    Postgres(Pool<Postgres>),
+}
+
+impl RateLimitStore {
+    pub fn configured(pool: &Pool<Postgres>) -> Self {
+        match Config::from_env().rate_limit_store.as_str() {
+            "memory" => RateLimitStore::Memory(Arc::default()),
+            "postgres" => RateLimitStore::Postgres(pool.clone()),
+            _ => panic!("RATE_LIMIT_STORE must be memory or postgres"),
+        }
+    }
+
+    /// Counts a request in the fixed window ending at `window_end` and returns the total.
+    async fn hit(&self, key: &str, window_end: i64) -> Result<u32, sqlx::Error> {
+        match self {
+            RateLimitStore::Memory(counters) => {
+                let mut counters = counters.lock().unwrap_or_else(|e| e.into_inner());
+                if counters.len() > MEMORY_STORE_PRUNE_THRESHOLD {
+                    let now = Utc::now().timestamp();
+                    counters.retain(|_, (end, _)| *end > now);
+                }
+
+                let counter = counters.entry(key.to_string()).or_insert((window_end, 0));
+                if counter.0 != window_end {
+                    *counter = (window_end, 0);
+                }
+                counter.1 += 1;
+                Ok(counter.1)
+            }
+            RateLimitStore::Postgres(pool) => {
+                let count = sqlx::query_scalar!(
+                    "INSERT INTO rate_limits (key, expires_at, count)
+                     VALUES ($1, to_timestamp($2), 1)
+                     ON CONFLICT (key) DO UPDATE SET
+                         count = CASE WHEN rate_limits.expires_at = EXCLUDED.expires_at
+                                      THEN rate_limits.count + 1 ELSE 1 END,
+                         expires_at = EXCLUDED.expires_at
+                     RETURNING count",
+                    key,
+                    window_end as f64
+                )
+                .fetch_one(pool)
+                .await?;
+
+                if count == 1 {
+                    sqlx::query!("DELETE FROM rate_limits WHERE expires_at < NOW()")
+                        .execute(pool)
+                        .await?;
+                }
+
+                Ok(count.max(0) as u32)
+            }
+        }
+    }
+}
+
+/// Fixed window rate limiting for selected routes.
+///
+/// Every rule matching the method and path (or route pattern, e.g.
+/// `/oidc/{provider}/callback`) of a request is counted. Responses carry the
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers of the rule
+/// closest to its limit, and requests over a limit get `429` with `Retry-After`. Counting
+/// failures let the request through.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    store: RateLimitStore,
+    rules: Arc<Vec<RateLimitRule>>,
+}
+
+impl RateLimiter {
+    pub fn new(store: RateLimitStore) -> Self {
+        RateLimiter {
+            store,
+            rules: Arc::new(Vec::new()),
+        }
+    }
+
+    pub fn rule(
+        mut self,
+        method: Method,
+        path: &'static str,
+        limit: u32,
+        window_seconds: i64,
+        key: RateLimitKey,
+    ) -> Self {
+        Arc::make_mut(&mut self.rules).push(RateLimitRule {
+            method,
+            path,
+            limit,
+            window_seconds,
+            key,
+        });
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+/// Counter state of one rule after the current request.
+struct Usage {
+    limit: u32,
+    remaining: u32,
+    reset: i64,
+    exceeded: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let pattern = req.match_pattern();
+            let rules: Vec<RateLimitRule> = limiter
+                .rules
+                .iter()
+                .filter(|rule| {
+                    rule.method == req.method()
+                        && (rule.path == req.path() || pattern.as_deref() == Some(rule.path))
+                })
+                .cloned()
+                .collect();
+
+            let mut tightest: Option<Usage> = None;
+            for rule in rules {
+                let subject = subject(&mut req, rule.key).await?;
+                let now = Utc::now().timestamp();
+                let window_end = now - now.rem_euclid(rule.window_seconds) + rule.window_seconds;
+                let key = format!("{} {}:{:?}:{subject}", rule.method, rule.path, rule.key);
+
+                let count = match limiter.store.hit(&key, window_end).await {
+                    Ok(count) => count,
+                    Err(e) => {
+                        eprintln!("Rate limit store error: {e}");
+                        continue;
+                    }
+                };
+
+                let usage = Usage {
+                    limit: rule.limit,
+                    remaining: rule.limit.saturating_sub(count),
+                    reset: window_end - now,
+                    exceeded: count > rule.limit,
+                };
+                let is_tighter = match &tightest {
+                    None => true,
+                    Some(current) if usage.exceeded != current.exceeded => usage.exceeded,
+                    Some(current) => usage.remaining < current.remaining,
+                };
+                if is_tighter {
+                    tightest = Some(usage);
+                }
+            }
+
+            if let Some(usage) = tightest.as_ref().filter(|usage| usage.exceeded) {
+                let mut response = HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, usage.reset.to_string()))
+                    .body(format!(
+                        "Too many requests, try again in {} seconds",
+                        usage.reset
+                    ));
+                insert_headers(response.headers_mut(), usage);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?.map_into_left_body();
+            if let Some(usage) = &tightest {
+                insert_headers(res.headers_mut(), usage);
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Value a rule is counted against for this request.
+async fn subject(req: &mut ServiceRequest, key: RateLimitKey) -> Result<String, Error> {
+    match key {
+        RateLimitKey::Ip => {}
+        RateLimitKey::UserId => {
+            let user_id = req
+                .headers()
+                .get("Authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .and_then(|token| validate_token(token.to_owned()).ok())
+                .map(|claims| claims.user_id);
+            if let Some(user_id) = user_id {
+                return Ok(format!("user:{user_id}"));
+            }
+        }
+        RateLimitKey::Email => {
+            // The body is read here and put back for the handler
+            let body = req.extract::<Bytes>().await?;
+            let email = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|value| value.get("email")?.as_str().map(str::to_lowercase));
+            req.set_payload(body.into());
+            if let Some(email) = email {
+                return Ok(format!("email:{email}"));
+            }
+        }
+    }
+
+    Ok(format!("ip:{}", client_ip(req.request())))
+}
+
+fn insert_headers(headers: &mut actix_web::http::header::HeaderMap, usage: &Usage) {
+    for (name, value) in [
+        ("ratelimit-limit", usage.limit.to_string()),
+        ("ratelimit-remaining", usage.remaining.to_string()),
+        ("ratelimit-reset", usage.reset.to_string()),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        RwLock,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use chrono::Utc;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{config::Config, middlewares::jwt::Claims};
+
+/// In-memory mirror of `revoked_tokens`, `users.tokens_revoked_at` and `sessions.revoked_at`.
+///
+/// Revocations made by this instance are visible immediately. Revocations made by other
+/// instances (or the admin CLI) are picked up on the next sync, at most
+/// `REVOCATION_SYNC_SECONDS` later.
+#[derive(Debug, Default)]
+pub struct RevocationCache {
+    /// Revoked `jti`s and the expiry of the token they belong to
+    tokens: RwLock<HashMap<Uuid, i64>>,
+    /// Users whose tokens issued before the millisecond are revoked. A token issued within
+    /// the millisecond of the revocation cannot be told apart from the ones issued right
+    /// after it, such as the new tokens of a password change, so it is kept.
+    users: RwLock<HashMap<Uuid, i64>>,
+    /// Revoked sessions and when they were revoked, every access token of theirs is revoked
+    sessions: RwLock<HashMap<Uuid, i64>>,
+    synced_at: AtomicI64,
+}
+
+impl RevocationCache {
+    pub fn revoke_token(&self, jti: Uuid, expires_at: i64) {
+        if let Ok(mut tokens) = self.tokens.write() {
+            tokens.insert(jti, expires_at);
+        }
+    }
+
+    pub fn revoke_user(&self, user_id: Uuid, revoked_at: i64) {
+        if let Ok(mut users) = self.users.write() {
+            let entry = users.entry(user_id).or_insert(revoked_at);
+            *entry = (*entry).max(revoked_at);
+        }
+    }
+
+    pub fn revoke_session(&self, session_id: Uuid, revoked_at: i64) {
+        if let Ok(mut sessions) = self.sessions.write() {
+            sessions.insert(session_id, revoked_at);
+        }
+    }
+
+    pub fn is_revoked(&self, claims: &Claims) -> bool {
+        let token_revoked = self
+            .tokens
+            .read()
+            .map(|tokens| tokens.contains_key(&claims.jti))
+            .unwrap_or(false);
+        let user_revoked = self
+            .users
+            .read()
+            .map(|users| {
+                users
+                    .get(&claims.user_id)
+                    .is_some_and(|revoked_at| claims.issued_at_millis() < *revoked_at)
+            })
+            .unwrap_or(false);
+
+        let session_revoked = claims.sid.is_some_and(|sid| {
+            self.sessions
+                .read()
+                .map(|sessions| sessions.contains_key(&sid))
+                .unwrap_or(false)
+        });
+
+        token_revoked || user_revoked || session_revoked
+    }
+
+    /// Reloads revocations from the database when the last sync is too old.
+    pub async fn sync_if_stale(&self, pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let now = Utc::now().timestamp();
+        let synced_at = self.synced_at.load(Ordering::Acquire);
+        if now - synced_at < Config::from_env().revocation_sync_seconds {
+            return Ok(());
+        }
+        // Only one request performs the sync, the others keep using the current data
+        if self
+            .synced_at
+            .compare_exchange(synced_at, now, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Ok(());
+        }
+
+        if let Err(e) = self.sync(pool).await {
+            self.synced_at.store(synced_at, Ordering::Release);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    async fn sync(&self, pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(pool)
+            .await?;
+
+        let tokens = sqlx::query!("SELECT jti, expires_at FROM revoked_tokens")
+            .fetch_all(pool)
+            .await?;
+
+        // Access tokens issued before the cutoff have expired on their own
+        let access_token_ttl_minutes = Config::from_env().access_token_ttl_minutes;
+        let users = sqlx::query!(
+            "SELECT id, tokens_revoked_at FROM users
+             WHERE tokens_revoked_at > NOW() - make_interval(mins => $1)",
+            access_token_ttl_minutes as i32
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let sessions = sqlx::query!(
+            r#"SELECT id, revoked_at as "revoked_at!" FROM sessions
+               WHERE revoked_at > NOW() - make_interval(mins => $1)"#,
+            access_token_ttl_minutes as i32
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let now = Utc::now().timestamp();
+        if let Ok(mut cached) = self.tokens.write() {
+            cached.retain(|_, expires_at| *expires_at >= now);
+            cached.extend(
+                tokens
+                    .into_iter()
+                    .map(|token| (token.jti, token.expires_at.timestamp())),
+            );
+        }
+        if let Ok(mut cached) = self.sessions.write() {
+            cached.retain(|_, revoked_at| *revoked_at >= now - access_token_ttl_minutes * 60);
+            cached.extend(
+                sessions
+                    .into_iter()
+                    .map(|session| (session.id, session.revoked_at.timestamp())),
+            );
+        }
+        for user in users {
+            if let Some(revoked_at) = user.tokens_revoked_at {
+                self.revoke_user(user.id, revoked_at.timestamp_millis());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::OnceLock;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header,
+    errors::{ErrorKind, Result},
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+        ThumbprintHash,
+    },
+};
+use pkcs1::{RsaPublicKey, der::Decode};
+use serde::de::DeserializeOwned;
+
+use crate::config::Config;
+
+static CONFIGURED: OnceLock<SigningKeys> = OnceLock::new();
+
+/// A public key accepted for token verification.
+struct VerificationKey {
+    kid: String,
+    algorithm: Algorithm,
+    decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+/// Keys used to sign and verify our JWTs, set by the `JWT_*` variables.
+///
+/// With `HS512` tokens are signed with `SECRET_KEY` and carry no `kid`. With `RS256` or
+/// `EdDSA` they are signed with the private key and name its public key in `kid`, and every
+/// public key, including the retired ones of `JWT_PREVIOUS_PUBLIC_KEYS`, is published as a
+/// JWKS. Tokens are verified with the key their `kid` names, or with `SECRET_KEY` when they
+/// have none and HS512 is still accepted.
+pub struct SigningKeys {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    encoding: EncodingKey,
+    verification: Vec<VerificationKey>,
+    secret: Option<DecodingKey>,
+}
+
+impl SigningKeys {
+    /// The keys from the environment, read on first use. Panics on invalid key settings,
+    /// so `main` loads them before serving requests.
+    pub fn configured() -> &'static SigningKeys {
+        CONFIGURED.get_or_init(Self::load)
+    }
+
+    fn load() -> Self {
+        let config = Config::from_env();
+        let algorithm = match config.jwt_algorithm.as_str() {
+            "HS512" => Algorithm::HS512,
+            "RS256" => Algorithm::RS256,
+            "EdDSA" => Algorithm::EdDSA,
+            _ => panic!("JWT_ALGORITHM must be HS512, RS256 or EdDSA"),
+        };
+
+        let mut verification = Vec::new();
+        let (encoding, kid) = if algorithm == Algorithm::HS512 {
+            (EncodingKey::from_secret(config.secret_key.as_bytes()), None)
+        } else {
+            let path = config
+                .jwt_private_key_file
+                .expect("JWT_PRIVATE_KEY_FILE must be set for asymmetric JWT signing");
+            let pem = std::fs::read(&path).expect("JWT_PRIVATE_KEY_FILE must be a readable file");
+            let encoding = match algorithm {
+                Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+                _ => EncodingKey::from_ed_pem(&pem),
+            }
+            .expect("JWT_PRIVATE_KEY_FILE must hold a PEM private key for JWT_ALGORITHM");
+
+            let public = VerificationKey::load(
+                &config
+                    .jwt_public_key_file
+                    .expect("JWT_PUBLIC_KEY_FILE must be set for asymmetric JWT signing"),
+                config.jwt_key_id,
+            );
+            if public.algorithm != algorithm || !public.matches(&encoding) {
+                panic!("JWT_PUBLIC_KEY_FILE must hold the public key of JWT_PRIVATE_KEY_FILE");
+            }
+
+            let kid = public.kid.clone();
+            verification.push(public);
+            (encoding, Some(kid))
+        };
+
+        for entry in &config.jwt_previous_public_keys {
+            let (kid, path) = match entry.split_once('=') {
+                Some((kid, path)) => (Some(kid.to_string()), path),
+                None => (None, entry.as_str()),
+            };
+            verification.push(VerificationKey::load(path, kid));
+        }
+
+        let secret = (algorithm == Algorithm::HS512 || config.jwt_accept_secret_key)
+            .then(|| DecodingKey::from_secret(config.secret_key.as_bytes()));
+
+        SigningKeys {
+            algorithm,
+            kid,
+            encoding,
+            verification,
+            secret,
+        }
+    }
+
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.kid.clone();
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding
+    }
+
+    /// Verifies a token with the key named by its header and returns its claims.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T> {
+        let header = decode_header(token)?;
+        let (key, algorithm) = match header.kid.as_deref() {
+            Some(kid) => self
+                .verification
+                .iter()
+                .find(|key| key.kid == kid)
+                .map(|key| (&key.decoding, key.algorithm)),
+            None => self.secret.as_ref().map(|key| (key, Algorithm::HS512)),
+        }
+        .ok_or(ErrorKind::InvalidToken)?;
+
+        decode::<T>(token, key, &Validation::new(algorithm)).map(|data| data.claims)
+    }
+
+    /// Public keys accepted for verification, empty when only HS512 is used.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification
+                .iter()
+                .map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+impl VerificationKey {
+    /// Reads an RSA or Ed25519 public key in PEM format, named `kid` or by its RFC 7638
+    /// thumbprint.
+    fn load(path: &str, kid: Option<String>) -> Self {
+        let pem = std::fs::read(path)
+            .unwrap_or_else(|_| panic!("JWT public key {path} must be a readable file"));
+
+        let (algorithm, parameters) = if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+            let public = RsaPublicKey::from_der(key.as_bytes())
+                .unwrap_or_else(|_| panic!("JWT public key {path} must be a public key"));
+            (
+                Algorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(public.modulus.as_bytes()),
+                    e: URL_SAFE_NO_PAD.encode(public.public_exponent.as_bytes()),
+                }),
+            )
+        } else if let Ok(key) = DecodingKey::from_ed_pem(&pem) {
+            (
+                Algorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+                }),
+            )
+        } else {
+            panic!("JWT public key {path} must be an RSA or Ed25519 public key in PEM format");
+        };
+
+        let mut jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(match algorithm {
+                    Algorithm::RS256 => KeyAlgorithm::RS256,
+                    _ => KeyAlgorithm::EdDSA,
+                }),
+                ..Default::default()
+            },
+            algorithm: parameters,
+        };
+        let kid = kid.unwrap_or_else(|| jwk.thumbprint(ThumbprintHash::SHA256));
+        jwk.common.key_id = Some(kid.clone());
+        let decoding = DecodingKey::from_jwk(&jwk)
+            .unwrap_or_else(|_| panic!("JWT public key {path} is not supported"));
+
+        VerificationKey {
+            kid,
+            algorithm,
+            decoding,
+            jwk,
+        }
+    }
+
+    /// Whether a token signed with `encoding` verifies with this key.
+    fn matches(&self, encoding: &EncodingKey) -> bool {
+        let mut validation = Validation::new(self.algorithm);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+
+        jsonwebtoken::encode(
+            &Header::new(self.algorithm),
+            &serde_json::json!({}),
+            encoding,
+        )
+        .map(|token| decode::<serde_json::Value>(&token, &self.decoding, &validation).is_ok())
+        .unwrap_or(false)
+    }
+}
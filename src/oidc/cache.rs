@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+
+use crate::{
+    config::OidcProviderConfig,
+    oidc::{entities::ProviderMetadata, errors::oidc::OidcErrors},
+};
+
+/// Seconds before a request to an identity provider is abandoned.
+const PROVIDER_TIMEOUT_SECONDS: u64 = 10;
+
+/// Discovery documents and signing keys of the configured identity providers.
+///
+/// Both are fetched on first use. Signing keys are fetched again whenever an ID token
+/// names a key that is not cached, which is how providers roll their keys over.
+#[derive(Debug)]
+pub struct ProviderCache {
+    client: reqwest::Client,
+    metadata: RwLock<HashMap<String, Arc<ProviderMetadata>>>,
+    keys: RwLock<HashMap<String, Arc<JwkSet>>>,
+}
+
+impl Default for ProviderCache {
+    fn default() -> Self {
+        ProviderCache {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(PROVIDER_TIMEOUT_SECONDS))
+                .build()
+                .expect("Failed to build the OIDC HTTP client"),
+            metadata: RwLock::default(),
+            keys: RwLock::default(),
+        }
+    }
+}
+
+impl ProviderCache {
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Discovery document of a provider, whose `issuer` must match the configured one.
+    pub async fn metadata(
+        &self,
+        provider: &OidcProviderConfig,
+    ) -> Result<Arc<ProviderMetadata>, OidcErrors> {
+        if let Some(metadata) = self
+            .metadata
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(&provider.name).cloned())
+        {
+            return Ok(metadata);
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = self.fetch(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            eprintln!(
+                "OIDC provider {} advertises issuer {}, expected {}",
+                provider.name, metadata.issuer, provider.issuer
+            );
+            return Err(OidcErrors::ProviderUnavailable);
+        }
+
+        let metadata = Arc::new(metadata);
+        if let Ok(mut cache) = self.metadata.write() {
+            cache.insert(provider.name.clone(), metadata.clone());
+        }
+        Ok(metadata)
+    }
+
+    /// Signing key named by `kid`, or the only key of the set when the token names none.
+    pub async fn key(
+        &self,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> Result<Jwk, OidcErrors> {
+        let cached = self
+            .keys
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(&provider.name).cloned());
+        if let Some(key) = cached.as_deref().and_then(|keys| Self::select(keys, kid)) {
+            return Ok(key);
+        }
+
+        let keys: Arc<JwkSet> = Arc::new(self.fetch(&metadata.jwks_uri).await?);
+        if let Ok(mut cache) = self.keys.write() {
+            cache.insert(provider.name.clone(), keys.clone());
+        }
+        Self::select(&keys, kid).ok_or(OidcErrors::InvalidIdToken)
+    }
+
+    fn select(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+        match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        }
+    }
+
+    async fn fetch<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, OidcErrors> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match response {
+            Ok(response) => response.json::<T>().await.map_err(|e| {
+                eprintln!("Invalid response from {url}: {e}");
+                OidcErrors::ProviderUnavailable
+            }),
+            Err(e) => {
+                eprintln!("Failed to fetch {url}: {e}");
+                Err(OidcErrors::ProviderUnavailable)
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct OidcCallbackRequest {
+    #[validate(length(min = 1, max = 2048))]
+    pub code: String,
+    #[validate(length(min = 32, max = 255))]
+    pub state: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcProvidersResponse {
+    pub providers: Vec<String>,
+}
+
+/// Successful response of a provider's token endpoint.
+#[derive(Debug, Deserialize)]
+pub struct TokenEndpointResponse {
+    pub id_token: Option<String>,
+}
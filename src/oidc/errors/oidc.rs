@@ -0,0 +1,41 @@
+use actix_failwrap::ErrorResponse;
+use thiserror::Error;
+
+#[derive(Debug, ErrorResponse, Error)]
+pub enum OidcErrors {
+    #[error("Unknown identity provider")]
+    #[status_code(404)]
+    UnknownProvider,
+
+    #[error("Invalid request")]
+    #[status_code(400)]
+    InvalidRequest,
+
+    #[error("Invalid or expired login state")]
+    #[status_code(400)]
+    InvalidState,
+
+    #[error("Authorization code was rejected by the identity provider")]
+    #[status_code(400)]
+    CodeRejected,
+
+    #[error("Invalid ID token")]
+    #[status_code(401)]
+    InvalidIdToken,
+
+    #[error("The identity provider did not share a verified email address")]
+    #[status_code(403)]
+    EmailNotVerified,
+
+    #[error("Identity provider is unavailable")]
+    #[status_code(502)]
+    ProviderUnavailable,
+
+    #[error("Default role not found")]
+    #[status_code(500)]
+    DefaultRoleNotFound,
+
+    #[error("Database error")]
+    #[status_code(500)]
+    DatabaseError,
+}
@@ -0,0 +1,20 @@
+pub mod entities {
+    mod identity;
+    mod provider;
+    pub use identity::*;
+    pub use provider::*;
+}
+
+mod cache;
+pub use cache::ProviderCache;
+
+mod dtos;
+pub use dtos::*;
+
+pub mod errors;
+
+mod routes;
+pub use routes::{api_config as api_routes, config as routes};
+
+mod service;
+pub use service::*;
@@ -0,0 +1,154 @@
+use actix_failwrap::proof_route;
+use actix_web::{
+    HttpResponse, Result,
+    http::header::LOCATION,
+    web::{self, Data, Json, Path},
+};
+use validator::Validate;
+
+use crate::{
+    AppState,
+    audit::{AuditLog, actions, entities::AuditEvent},
+    middlewares::jwt::Claims,
+    oidc::{OidcCallbackRequest, OidcProvidersResponse, OidcService, errors::oidc::OidcErrors},
+    users::{
+        MfaService,
+        entities::{DeviceInfo, PartialUser},
+        errors::auth::AuthErrors,
+    },
+};
+
+/// Configure OpenID Connect login routes
+///
+/// `GET` `/oidc/providers` - List the configured identity provider names
+///
+/// `GET` `/oidc/{provider}/authorize` - Redirect to the provider's login page
+///
+/// Starts an authorization code login with PKCE. The provider sends the user back to
+/// the configured redirect URI with `code` and `state` query parameters.
+///
+/// `POST` `/oidc/{provider}/callback` - Exchange the code and state for tokens
+///
+/// Responds like `/login`, including the `mfa_pending` token for users with two-factor
+/// authentication. A login state can be used once and expires after 10 minutes.
+/// OIDC Callback Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct OidcCallbackRequest {
+///     #[validate(length(min = 1, max = 2048))]
+///     pub code: String,
+///     #[validate(length(min = 32, max = 255))]
+///     pub state: String,
+/// }
+/// ```
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_providers)
+        .service(authorize)
+        .service(callback);
+}
+
+/// Configure authenticated OpenID Connect routes, mounted under the `/api` scope
+///
+/// `GET` `/api/me/identities` - List the external identities linked to the current user
+///
+/// User Identity entity:
+/// ```ignore
+/// #[derive(Debug, Serialize, FromRow)]
+/// pub struct UserIdentity {
+///     pub id: Uuid,
+///     pub provider: String,
+///     pub subject: String,
+///     pub email: Option<String>,
+///     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+///     pub last_login_at: Option<chrono::DateTime<chrono::Utc>>,
+/// }
+/// ```
+pub fn api_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_identities);
+}
+
+#[proof_route("GET /oidc/providers")]
+async fn list_providers() -> Result<HttpResponse, OidcErrors> {
+    Ok(HttpResponse::Ok().json(OidcProvidersResponse {
+        providers: OidcService::provider_names(),
+    }))
+}
+
+#[proof_route("GET /oidc/{provider}/authorize")]
+async fn authorize(
+    state: Data<AppState>,
+    provider: Path<String>,
+) -> Result<HttpResponse, OidcErrors> {
+    let url = OidcService::authorization_url(&state, &provider).await?;
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .finish())
+}
+
+#[proof_route("POST /oidc/{provider}/callback")]
+async fn callback(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    provider: Path<String>,
+    body: Json<OidcCallbackRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    body.validate()
+        .map_err(|_| Into::<actix_web::Error>::into(OidcErrors::InvalidRequest))?;
+
+    let failure = || {
+        AuditEvent::failure(actions::LOGIN, &device)
+            .detail("method", "oidc")
+            .detail("provider", provider.as_str())
+    };
+
+    let user_id =
+        match OidcService::complete_login(&state, &provider, &body.code, &body.state).await {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                AuditLog::record(&state, failure().reason(&e));
+                return Err(e.into());
+            }
+        };
+    let email = PartialUser::find_by_id(&state, user_id)
+        .await
+        .map_err(Into::<actix_web::Error>::into)?
+        .email;
+    let user = PartialUser::authenticate_user(&state, &email)
+        .await
+        .map_err(Into::<actix_web::Error>::into)?;
+
+    if !user.is_active {
+        AuditLog::record(
+            &state,
+            failure()
+                .subject(user.id)
+                .reason(AuthErrors::AccountDeactivated),
+        );
+        return Err(AuthErrors::AccountDeactivated.into());
+    }
+
+    let success = AuditEvent::success(actions::LOGIN, &device)
+        .actor(user.id)
+        .subject(user.id)
+        .detail("method", "oidc")
+        .detail("provider", provider.as_str());
+    if user.mfa_enabled {
+        AuditLog::record(&state, success.detail("mfa_pending", true));
+        return Ok(HttpResponse::Ok().json(MfaService::challenge(user.id)));
+    }
+
+    let tokens = PartialUser::issue_tokens(&state, user.id, user.roles, &device)
+        .await
+        .map_err(Into::<actix_web::Error>::into)?;
+    AuditLog::record(&state, success);
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[proof_route("GET /me/identities")]
+async fn list_identities(
+    state: Data<AppState>,
+    claims: Claims,
+) -> Result<HttpResponse, OidcErrors> {
+    let identities = OidcService::identities(&state, claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(identities))
+}
@@ -0,0 +1,306 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    config::{Config, OidcProviderConfig},
+    helpers::token::{generate_opaque_token, hash_token},
+    oidc::{
+        TokenEndpointResponse,
+        entities::{IdTokenClaims, ProviderMetadata, UserIdentity},
+        errors::oidc::OidcErrors,
+    },
+    users::{AuthUser, entities::PartialUser, errors::auth::AuthErrors},
+};
+
+/// Minutes a user has to come back from the provider with the authorization code.
+const LOGIN_STATE_TTL_MINUTES: i32 = 10;
+
+/// Signature algorithms accepted for ID tokens, symmetric ones would be keyed with the
+/// client secret and are never accepted.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// OpenID Connect authorization code login with PKCE.
+///
+/// Users are found by the provider's subject in `user_identities`. An unknown subject is
+/// linked to the user holding the same email, or to a new user, but only when the
+/// provider reports that email as verified.
+pub struct OidcService;
+
+impl OidcService {
+    pub fn provider(name: &str) -> Result<OidcProviderConfig, OidcErrors> {
+        Config::from_env()
+            .oidc_providers
+            .into_iter()
+            .find(|provider| provider.name == name)
+            .ok_or(OidcErrors::UnknownProvider)
+    }
+
+    pub fn provider_names() -> Vec<String> {
+        Config::from_env()
+            .oidc_providers
+            .into_iter()
+            .map(|provider| provider.name)
+            .collect()
+    }
+
+    /// Stores a new login state and returns the provider URL to send the user to.
+    pub async fn authorization_url(
+        state: &AppState,
+        provider_name: &str,
+    ) -> Result<String, OidcErrors> {
+        let provider = Self::provider(provider_name)?;
+        let metadata = state.oidc.metadata(&provider).await?;
+
+        let login_state = generate_opaque_token();
+        let nonce = generate_opaque_token();
+        let code_verifier = format!("{}{}", generate_opaque_token(), generate_opaque_token());
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        sqlx::query!("DELETE FROM oidc_login_states WHERE expires_at < NOW()")
+            .execute(&state.db_pool)
+            .await
+            .map_err(|_| OidcErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "INSERT INTO oidc_login_states (state_hash, provider, nonce, code_verifier, expires_at)
+             VALUES ($1, $2, $3, $4, NOW() + make_interval(mins => $5))",
+            hash_token(&login_state),
+            provider.name,
+            nonce,
+            code_verifier,
+            LOGIN_STATE_TTL_MINUTES
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| OidcErrors::DatabaseError)?;
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+            .map_err(|_| OidcErrors::ProviderUnavailable)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_uri)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", &login_state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.into())
+    }
+
+    /// Completes a login started by `authorization_url` and returns the user it belongs to.
+    ///
+    /// The login state is consumed first, so a code and state pair works at most once.
+    pub async fn complete_login(
+        state: &AppState,
+        provider_name: &str,
+        code: &str,
+        login_state: &str,
+    ) -> Result<Uuid, OidcErrors> {
+        let provider = Self::provider(provider_name)?;
+
+        let pending = sqlx::query!(
+            "DELETE FROM oidc_login_states
+             WHERE state_hash = $1 AND provider = $2 AND expires_at > NOW()
+             RETURNING nonce, code_verifier",
+            hash_token(login_state),
+            provider.name
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| OidcErrors::DatabaseError)?
+        .ok_or(OidcErrors::InvalidState)?;
+
+        let metadata = state.oidc.metadata(&provider).await?;
+        let id_token =
+            Self::exchange_code(state, &provider, &metadata, code, &pending.code_verifier).await?;
+        let claims = Self::validate_id_token(state, &provider, &metadata, &id_token).await?;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(OidcErrors::InvalidIdToken);
+        }
+
+        Self::resolve_user(state, &provider, &claims).await
+    }
+
+    /// Identities linked to a user, oldest first.
+    pub async fn identities(
+        state: &AppState,
+        user_id: Uuid,
+    ) -> Result<Vec<UserIdentity>, OidcErrors> {
+        sqlx::query_as::<_, UserIdentity>(
+            "SELECT id, provider, subject, email, created_at, last_login_at
+             FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| OidcErrors::DatabaseError)
+    }
+
+    async fn exchange_code(
+        state: &AppState,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, OidcErrors> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let response = state
+            .oidc
+            .client()
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| {
+                eprintln!(
+                    "Failed to reach the token endpoint of {}: {e}",
+                    provider.name
+                );
+                OidcErrors::ProviderUnavailable
+            })?;
+
+        if response.status().is_client_error() {
+            return Err(OidcErrors::CodeRejected);
+        }
+        if !response.status().is_success() {
+            eprintln!(
+                "Token endpoint of {} answered {}",
+                provider.name,
+                response.status()
+            );
+            return Err(OidcErrors::ProviderUnavailable);
+        }
+
+        response
+            .json::<TokenEndpointResponse>()
+            .await
+            .map_err(|_| OidcErrors::ProviderUnavailable)?
+            .id_token
+            .ok_or(OidcErrors::InvalidIdToken)
+    }
+
+    /// Checks the signature, issuer, audience and expiry of an ID token.
+    async fn validate_id_token(
+        state: &AppState,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, OidcErrors> {
+        let header = decode_header(id_token).map_err(|_| OidcErrors::InvalidIdToken)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcErrors::InvalidIdToken);
+        }
+
+        let jwk = state
+            .oidc
+            .key(provider, metadata, header.kid.as_deref())
+            .await?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| OidcErrors::InvalidIdToken)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| OidcErrors::InvalidIdToken)
+    }
+
+    /// Finds the user linked to the token's subject, linking or provisioning one by email.
+    async fn resolve_user(
+        state: &AppState,
+        provider: &OidcProviderConfig,
+        claims: &IdTokenClaims,
+    ) -> Result<Uuid, OidcErrors> {
+        let linked = sqlx::query_scalar!(
+            "UPDATE user_identities
+             SET last_login_at = NOW(), email = COALESCE($3, email)
+             WHERE provider = $1 AND subject = $2
+             RETURNING user_id",
+            provider.name,
+            claims.sub,
+            claims.verified_email()
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| OidcErrors::DatabaseError)?;
+
+        if let Some(user_id) = linked {
+            return Ok(user_id);
+        }
+
+        let email = claims
+            .verified_email()
+            .ok_or(OidcErrors::EmailNotVerified)?;
+        let user_id = match PartialUser::find_by_email(state, email).await {
+            Ok(user) => user.id,
+            Err(AuthErrors::UserNotFound) => Self::provision_user(state, email).await?,
+            Err(_) => return Err(OidcErrors::DatabaseError),
+        };
+
+        PartialUser::mark_email_verified(state, user_id)
+            .await
+            .map_err(|_| OidcErrors::DatabaseError)?;
+
+        // A concurrent first login with the same subject may have linked it already
+        sqlx::query_scalar!(
+            "INSERT INTO user_identities (user_id, provider, subject, email, last_login_at)
+             VALUES ($1, $2, $3, $4, NOW())
+             ON CONFLICT (provider, subject) DO UPDATE SET last_login_at = NOW()
+             RETURNING user_id",
+            user_id,
+            provider.name,
+            claims.sub,
+            email
+        )
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| OidcErrors::DatabaseError)
+    }
+
+    /// Creates a user with the `OIDC_DEFAULT_ROLE` and a random password, which can be
+    /// replaced through the password reset flow.
+    async fn provision_user(state: &AppState, email: &str) -> Result<Uuid, OidcErrors> {
+        let auth_user = AuthUser {
+            email: email.to_string(),
+            password: generate_opaque_token(),
+        };
+        let role = Config::from_env().oidc_default_role;
+
+        match PartialUser::create_user_with_role(state, &auth_user, &role).await {
+            Ok(user_id) => Ok(user_id),
+            Err(AuthErrors::RoleNotFound) => Err(OidcErrors::DefaultRoleNotFound),
+            Err(AuthErrors::EmailAlreadyRegistered) => PartialUser::find_by_email(state, email)
+                .await
+                .map(|user| user.id)
+                .map_err(|_| OidcErrors::DatabaseError),
+            Err(_) => Err(OidcErrors::DatabaseError),
+        }
+    }
+}
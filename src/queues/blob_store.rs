@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AppState, config::Config, queues::errors::queue::QueueErrors};
+
+/// Storage for payloads too large to live inside a pgmq row.
+///
+/// The store used for a blob is recorded in the message referencing it, so changing
+/// `QUEUE_BLOB_STORE` doesn't orphan blobs written before the change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobStore {
+    Postgres,
+    Filesystem,
+}
+
+impl BlobStore {
+    pub fn configured() -> Self {
+        match Config::from_env().queue_blob_store.as_str() {
+            "postgres" => BlobStore::Postgres,
+            "filesystem" => BlobStore::Filesystem,
+            _ => panic!("QUEUE_BLOB_STORE must be postgres or filesystem"),
+        }
+    }
+
+    pub async fn put(
+        self,
+        state: &AppState,
+        queue: &str,
+        id: Uuid,
+        data: Vec<u8>,
+    ) -> Result<(), QueueErrors> {
+        match self {
+            BlobStore::Postgres => {
+                sqlx::query!(
+                    "INSERT INTO queue_blobs (id, queue_name, data) VALUES ($1, $2, $3)",
+                    id,
+                    queue,
+                    data
+                )
+                .execute(&state.db_pool)
+                .await
+                .map_err(|_| QueueErrors::DatabaseError)?;
+                Ok(())
+            }
+            BlobStore::Filesystem => {
+                let dir = PathBuf::from(Config::from_env().queue_blob_dir);
+                actix_web::rt::task::spawn_blocking(move || {
+                    std::fs::create_dir_all(&dir)?;
+                    std::fs::write(dir.join(id.to_string()), data)
+                })
+                .await
+                .map_err(|_| QueueErrors::BlobStoreError)?
+                .map_err(|_| QueueErrors::BlobStoreError)
+            }
+        }
+    }
+
+    pub async fn get(self, state: &AppState, id: Uuid) -> Result<Vec<u8>, QueueErrors> {
+        match self {
+            BlobStore::Postgres => sqlx::query!("SELECT data FROM queue_blobs WHERE id = $1", id)
+                .fetch_optional(&state.db_pool)
+                .await
+                .map_err(|_| QueueErrors::DatabaseError)?
+                .map(|record| record.data)
+                .ok_or(QueueErrors::BlobNotFound),
+            BlobStore::Filesystem => {
+                let path = PathBuf::from(Config::from_env().queue_blob_dir).join(id.to_string());
+                actix_web::rt::task::spawn_blocking(move || std::fs::read(path))
+                    .await
+                    .map_err(|_| QueueErrors::BlobStoreError)?
+                    .map_err(|_| QueueErrors::BlobNotFound)
+            }
+        }
+    }
+
+    pub async fn delete(self, state: &AppState, id: Uuid) -> Result<(), QueueErrors> {
+        match self {
+            BlobStore::Postgres => {
+                sqlx::query!("DELETE FROM queue_blobs WHERE id = $1", id)
+                    .execute(&state.db_pool)
+                    .await
+                    .map_err(|_| QueueErrors::DatabaseError)?;
+                Ok(())
+            }
+            BlobStore::Filesystem => {
+                let path = PathBuf::from(Config::from_env().queue_blob_dir).join(id.to_string());
+                actix_web::rt::task::spawn_blocking(move || match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                    _ => Ok(()),
+                })
+                .await
+                .map_err(|_| QueueErrors::BlobStoreError)?
+                .map_err(|_| QueueErrors::BlobStoreError)
+            }
+        }
+    }
+}
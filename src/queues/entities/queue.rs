@@ -0,0 +1,29 @@
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct QueueInfo {
+    pub queue_name: String,
+    pub is_partitioned: bool,
+    pub is_unlogged: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct QueueMetrics {
+    pub queue_name: String,
+    pub queue_length: i64,
+    pub newest_msg_age_sec: Option<i32>,
+    pub oldest_msg_age_sec: Option<i32>,
+    pub total_messages: i64,
+    pub scrape_time: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct QueueMessage {
+    pub msg_id: i64,
+    pub read_ct: i32,
+    pub enqueued_at: chrono::DateTime<chrono::Utc>,
+    pub vt: chrono::DateTime<chrono::Utc>,
+    pub message: serde_json::Value,
+}
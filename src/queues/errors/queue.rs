@@ -0,0 +1,41 @@
+use actix_failwrap::ErrorResponse;
+use thiserror::Error;
+
+#[derive(Debug, ErrorResponse, Error)]
+pub enum QueueErrors {
+    #[error("Invalid queue name")]
+    #[status_code(400)]
+    InvalidQueueName,
+
+    #[error("Queue not found")]
+    #[status_code(404)]
+    QueueNotFound,
+
+    #[error("Message not found")]
+    #[status_code(404)]
+    MessageNotFound,
+
+    #[error("Message is being processed by another request")]
+    #[status_code(409)]
+    MessageLocked,
+
+    #[error("Message payload is too large")]
+    #[status_code(413)]
+    PayloadTooLarge,
+
+    #[error("Invalid message payload")]
+    #[status_code(500)]
+    PayloadError,
+
+    #[error("Message payload not found")]
+    #[status_code(500)]
+    BlobNotFound,
+
+    #[error("Message payload storage error")]
+    #[status_code(500)]
+    BlobStoreError,
+
+    #[error("Database error")]
+    #[status_code(500)]
+    DatabaseError,
+}
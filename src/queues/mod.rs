@@ -0,0 +1,14 @@
+pub mod entities {
+    mod queue;
+    pub use queue::*;
+}
+
+mod blob_store;
+pub use blob_store::*;
+
+pub mod errors;
+
+pub mod payload;
+
+mod service;
+pub use service::*;
@@ -0,0 +1,198 @@
+use std::io::{Read, Write};
+
+use actix_web::web;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    config::Config,
+    queues::{BlobStore, errors::queue::QueueErrors},
+};
+
+/// Key of the object wrapping an encoded payload. Messages without it are stored as-is.
+const ENVELOPE_KEY: &str = "$pgmq_payload";
+/// Largest payload accepted, so decoding a message never inflates past it.
+const MAX_PAYLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "encoding", rename_all = "snake_case")]
+enum Envelope {
+    /// Gzip compressed JSON, base64 encoded
+    Gzip { data: String },
+    /// Gzip compressed JSON kept outside of the message
+    Blob { blob_id: Uuid, store: BlobStore },
+}
+
+/// Encodes a message before it is sent to pgmq.
+///
+/// Payloads above `QUEUE_COMPRESSION_THRESHOLD` bytes are compressed, and compressed
+/// payloads still above `QUEUE_OFFLOAD_THRESHOLD` bytes are moved to the blob store.
+/// Payloads above `MAX_PAYLOAD_BYTES` are refused.
+pub async fn encode(
+    state: &AppState,
+    queue: &str,
+    message: &serde_json::Value,
+) -> Result<serde_json::Value, QueueErrors> {
+    let config = Config::from_env();
+    let raw = serde_json::to_vec(message).map_err(|_| QueueErrors::PayloadError)?;
+    if raw.len() > MAX_PAYLOAD_BYTES {
+        return Err(QueueErrors::PayloadTooLarge);
+    }
+    if raw.len() <= config.queue_compression_threshold {
+        return Ok(message.clone());
+    }
+
+    let compressed = web::block(move || compress(&raw))
+        .await
+        .map_err(|_| QueueErrors::PayloadError)??;
+    let envelope = if compressed.len() > config.queue_offload_threshold {
+        let blob_id = Uuid::new_v4();
+        let store = BlobStore::configured();
+        store.put(state, queue, blob_id, compressed).await?;
+        Envelope::Blob { blob_id, store }
+    } else {
+        Envelope::Gzip {
+            data: STANDARD.encode(compressed),
+        }
+    };
+
+    let envelope = serde_json::to_value(envelope).map_err(|_| QueueErrors::PayloadError)?;
+    Ok(serde_json::json!({ ENVELOPE_KEY: envelope }))
+}
+
+/// Restores a message read from pgmq to the payload originally sent.
+pub async fn decode(
+    state: &AppState,
+    message: serde_json::Value,
+) -> Result<serde_json::Value, QueueErrors> {
+    let Some(envelope) = envelope(&message) else {
+        return Ok(message);
+    };
+
+    let compressed = match envelope {
+        Envelope::Gzip { data } => STANDARD
+            .decode(data)
+            .map_err(|_| QueueErrors::PayloadError)?,
+        Envelope::Blob { blob_id, store } => store.get(state, blob_id).await?,
+    };
+
+    inflate(compressed).await
+}
+
+/// Decompresses and parses a payload on the blocking thread pool.
+async fn inflate(compressed: Vec<u8>) -> Result<serde_json::Value, QueueErrors> {
+    let raw = web::block(move || decompress(&compressed))
+        .await
+        .map_err(|_| QueueErrors::PayloadError)??;
+    serde_json::from_slice(&raw).map_err(|_| QueueErrors::PayloadError)
+}
+
+/// Deletes the blob referenced by an encoded message, if any.
+pub async fn delete_blob(state: &AppState, message: &serde_json::Value) -> Result<(), QueueErrors> {
+    if let Some(Envelope::Blob { blob_id, store }) = envelope(message) {
+        store.delete(state, blob_id).await?;
+    }
+
+    Ok(())
+}
+
+fn envelope(message: &serde_json::Value) -> Option<Envelope> {
+    let object = message.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+
+    serde_json::from_value(object.get(ENVELOPE_KEY)?.clone()).ok()
+}
+
+fn compress(raw: &[u8]) -> Result<Vec<u8>, QueueErrors> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(raw)
+        .map_err(|_| QueueErrors::PayloadError)?;
+    encoder.finish().map_err(|_| QueueErrors::PayloadError)
+}
+
+/// Decompresses a payload, refusing one that inflates past `MAX_PAYLOAD_BYTES`.
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, QueueErrors> {
+    let mut raw = Vec::new();
+    GzDecoder::new(compressed)
+        .take(MAX_PAYLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut raw)
+        .map_err(|_| QueueErrors::PayloadError)?;
+    if raw.len() > MAX_PAYLOAD_BYTES {
+        return Err(QueueErrors::PayloadTooLarge);
+    }
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn decompress_restores_compressed_bytes() {
+        let raw = json!({ "items": vec!["payload"; 1000] })
+            .to_string()
+            .into_bytes();
+        let compressed = compress(&raw).unwrap();
+        assert!(compressed.len() < raw.len());
+        assert_eq!(decompress(&compressed).unwrap(), raw);
+    }
+
+    #[test]
+    fn decompress_refuses_payloads_above_the_limit() {
+        let compressed = compress(&vec![0; MAX_PAYLOAD_BYTES + 1]).unwrap();
+        assert!(matches!(
+            decompress(&compressed),
+            Err(QueueErrors::PayloadTooLarge)
+        ));
+        assert!(matches!(
+            decompress(b"not gzip"),
+            Err(QueueErrors::PayloadError)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn gzip_envelope_round_trip() {
+        let message = json!({ "items": vec!["payload"; 1000] });
+        let compressed = compress(&serde_json::to_vec(&message).unwrap()).unwrap();
+        let encoded = json!({
+            ENVELOPE_KEY: Envelope::Gzip { data: STANDARD.encode(compressed) }
+        });
+
+        let Some(Envelope::Gzip { data }) = envelope(&encoded) else {
+            panic!("Expected a gzip envelope");
+        };
+        let restored = inflate(STANDARD.decode(data).unwrap()).await.unwrap();
+        assert_eq!(restored, message);
+    }
+
+    #[test]
+    fn blob_envelope_round_trip() {
+        let blob_id = Uuid::new_v4();
+        let encoded = json!({
+            ENVELOPE_KEY: Envelope::Blob { blob_id, store: BlobStore::Filesystem }
+        });
+
+        assert!(matches!(
+            envelope(&encoded),
+            Some(Envelope::Blob { blob_id: id, store: BlobStore::Filesystem }) if id == blob_id
+        ));
+    }
+
+    #[test]
+    fn plain_messages_are_not_envelopes() {
+        assert!(envelope(&json!({ "order": 1 })).is_none());
+        assert!(envelope(&json!([1, 2, 3])).is_none());
+        assert!(
+            envelope(&json!({ ENVELOPE_KEY: { "encoding": "gzip", "data": "" }, "order": 1 }))
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use sqlx::PgConnection;
+
+use crate::{
+    AppState,
+    queues::{
+        entities::{QueueInfo, QueueMessage, QueueMetrics},
+        errors::queue::QueueErrors,
+        payload,
+    },
+};
+
+/// Suffix used for the dead letter queue paired with every queue.
+pub const DEAD_LETTER_SUFFIX: &str = "_dlq";
+/// Longest queue name pgmq accepts.
+const MAX_QUEUE_NAME_LENGTH: usize = 47;
+
+pub struct QueueService;
+
+impl QueueService {
+    pub async fn list_queues(state: &AppState) -> Result<Vec<QueueInfo>, QueueErrors> {
+        sqlx::query_as::<_, QueueInfo>(
+            "SELECT queue_name::TEXT AS queue_name, is_partitioned, is_unlogged, created_at
+             FROM pgmq.list_queues()
+             ORDER BY queue_name",
+        )
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)
+    }
+
+    pub async fn metrics(state: &AppState, queue: &str) -> Result<QueueMetrics, QueueErrors> {
+        Self::ensure_exists(state, queue).await?;
+
+        sqlx::query_as::<_, QueueMetrics>(
+            "SELECT queue_name, queue_length, newest_msg_age_sec, oldest_msg_age_sec,
+                    total_messages, scrape_time
+             FROM pgmq.metrics($1)",
+        )
+        .bind(queue)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)
+    }
+
+    /// Returns the oldest messages of a queue without changing their visibility.
+    pub async fn peek(
+        state: &AppState,
+        queue: &str,
+        limit: i64,
+    ) -> Result<Vec<QueueMessage>, QueueErrors> {
+        Self::ensure_exists(state, queue).await?;
+
+        let messages = sqlx::query_as::<_, QueueMessage>(&format!(
+            "SELECT msg_id, read_ct, enqueued_at, vt, message
+             FROM pgmq.\"q_{queue}\"
+             ORDER BY msg_id
+             LIMIT $1"
+        ))
+        .bind(limit)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?;
+
+        Self::decode_messages(state, messages).await
+    }
+
+    /// Creates a queue. Creating a queue that already exists is a no-op.
+    pub async fn create(state: &AppState, queue: &str) -> Result<(), QueueErrors> {
+        Self::validate_queue_name(queue)?;
+
+        sqlx::query("SELECT pgmq.create($1)")
+            .bind(queue)
+            .execute(&state.db_pool)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Sends a message, compressing or offloading large payloads (see [`payload::encode`]).
+    pub async fn send(
+        state: &AppState,
+        queue: &str,
+        message: &serde_json::Value,
+    ) -> Result<i64, QueueErrors> {
+        let mut conn = state
+            .db_pool
+            .acquire()
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        Self::send_in(state, &mut conn, queue, message).await
+    }
+
+    /// Sends a message on `conn`, so it is only delivered once the caller's transaction
+    /// commits. An offloaded payload is written beforehand and outlives a rollback.
+    pub async fn send_in(
+        state: &AppState,
+        conn: &mut PgConnection,
+        queue: &str,
+        message: &serde_json::Value,
+    ) -> Result<i64, QueueErrors> {
+        Self::validate_queue_name(queue)?;
+        let encoded = payload::encode(state, queue, message).await?;
+
+        let result = sqlx::query_scalar::<_, i64>("SELECT * FROM pgmq.send($1, $2)")
+            .bind(queue)
+            .bind(&encoded)
+            .fetch_one(&mut *conn)
+            .await;
+
+        match result {
+            Ok(msg_id) => Ok(msg_id),
+            Err(_) => {
+                let _ = payload::delete_blob(state, &encoded).await;
+                Err(QueueErrors::DatabaseError)
+            }
+        }
+    }
+
+    pub async fn read(
+        state: &AppState,
+        queue: &str,
+        visibility_timeout_secs: i32,
+        quantity: i32,
+    ) -> Result<Vec<QueueMessage>, QueueErrors> {
+        Self::validate_queue_name(queue)?;
+
+        let messages = sqlx::query_as::<_, QueueMessage>(
+            "SELECT msg_id, read_ct, enqueued_at, vt, message FROM pgmq.read($1, $2, $3)",
+        )
+        .bind(queue)
+        .bind(visibility_timeout_secs)
+        .bind(quantity)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?;
+
+        Self::decode_messages(state, messages).await
+    }
+
+    /// Deletes a message along with its offloaded payload.
+    pub async fn delete(state: &AppState, queue: &str, msg_id: i64) -> Result<(), QueueErrors> {
+        Self::validate_queue_name(queue)?;
+        let message = Self::raw_message(state, queue, msg_id).await?;
+
+        let deleted = sqlx::query_scalar::<_, bool>("SELECT pgmq.delete($1, $2)")
+            .bind(queue)
+            .bind(msg_id)
+            .fetch_one(&state.db_pool)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        if !deleted {
+            return Err(QueueErrors::MessageNotFound);
+        }
+
+        payload::delete_blob(state, &message).await
+    }
+
+    /// Deletes a message on `conn` and returns it as stored, `None` when it is already gone.
+    ///
+    /// Its offloaded payload is left in place, since the deletion may still be rolled back:
+    /// pass the returned message to [`payload::delete_blob`] once the transaction commits.
+    pub async fn delete_in(
+        conn: &mut PgConnection,
+        queue: &str,
+        msg_id: i64,
+    ) -> Result<Option<serde_json::Value>, QueueErrors> {
+        Self::validate_queue_name(queue)?;
+
+        sqlx::query_scalar::<_, serde_json::Value>(&format!(
+            "DELETE FROM pgmq.\"q_{queue}\" WHERE msg_id = $1 RETURNING message"
+        ))
+        .bind(msg_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)
+    }
+
+    /// Archives a message. Offloaded payloads are deleted, only the reference is archived.
+    pub async fn archive(state: &AppState, queue: &str, msg_id: i64) -> Result<(), QueueErrors> {
+        Self::validate_queue_name(queue)?;
+        let message = Self::raw_message(state, queue, msg_id).await?;
+
+        let archived = sqlx::query_scalar::<_, bool>("SELECT pgmq.archive($1, $2)")
+            .bind(queue)
+            .bind(msg_id)
+            .fetch_one(&state.db_pool)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        if !archived {
+            return Err(QueueErrors::MessageNotFound);
+        }
+
+        payload::delete_blob(state, &message).await
+    }
+
+    pub fn dead_letter_queue_name(queue: &str) -> String {
+        format!("{queue}{DEAD_LETTER_SUFFIX}")
+    }
+
+    /// Moves a message into the queue's dead letter queue, creating it if needed.
+    pub async fn move_to_dead_letter(
+        state: &AppState,
+        queue: &str,
+        msg_id: i64,
+    ) -> Result<i64, QueueErrors> {
+        Self::ensure_exists(state, queue).await?;
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+        let dead_letter_id = Self::move_to_dead_letter_in(&mut tx, queue, msg_id).await?;
+        tx.commit().await.map_err(|_| QueueErrors::DatabaseError)?;
+
+        Ok(dead_letter_id)
+    }
+
+    /// [`QueueService::move_to_dead_letter`] on `conn`, for a queue known to exist.
+    pub async fn move_to_dead_letter_in(
+        conn: &mut PgConnection,
+        queue: &str,
+        msg_id: i64,
+    ) -> Result<i64, QueueErrors> {
+        Self::validate_queue_name(queue)?;
+        let dead_letter_queue = Self::dead_letter_queue_name(queue);
+
+        sqlx::query("SELECT pgmq.create($1)")
+            .bind(&dead_letter_queue)
+            .execute(&mut *conn)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        let message = sqlx::query_scalar::<_, serde_json::Value>(&format!(
+            "SELECT message FROM pgmq.\"q_{queue}\" WHERE msg_id = $1"
+        ))
+        .bind(msg_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?
+        .ok_or(QueueErrors::MessageNotFound)?;
+
+        let dead_letter_id = sqlx::query_scalar::<_, i64>("SELECT * FROM pgmq.send($1, $2)")
+            .bind(&dead_letter_queue)
+            .bind(&message)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        sqlx::query("SELECT pgmq.delete($1, $2)")
+            .bind(queue)
+            .bind(msg_id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        Ok(dead_letter_id)
+    }
+
+    /// Sends dead letter messages back to their original queue.
+    ///
+    /// When `msg_ids` is empty every message in the dead letter queue that is not being
+    /// requeued concurrently is requeued. Returns the new message ids in the original queue.
+    /// Fails with `MessageLocked` when a requested message is being requeued concurrently.
+    pub async fn requeue_dead_letters(
+        state: &AppState,
+        queue: &str,
+        msg_ids: &[i64],
+    ) -> Result<Vec<i64>, QueueErrors> {
+        Self::ensure_exists(state, queue).await?;
+        let dead_letter_queue = Self::dead_letter_queue_name(queue);
+        Self::ensure_exists(state, &dead_letter_queue).await?;
+
+        let mut msg_ids = msg_ids.to_vec();
+        msg_ids.sort_unstable();
+        msg_ids.dedup();
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+
+        let messages = sqlx::query_as::<_, QueueMessage>(&format!(
+            "SELECT msg_id, read_ct, enqueued_at, vt, message
+             FROM pgmq.\"q_{dead_letter_queue}\"
+             WHERE cardinality($1::BIGINT[]) = 0 OR msg_id = ANY($1)
+             ORDER BY msg_id
+             FOR UPDATE SKIP LOCKED"
+        ))
+        .bind(&msg_ids)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?;
+
+        if !msg_ids.is_empty() && messages.len() != msg_ids.len() {
+            // Skipped rows either do not exist or are locked by another requeue
+            let existing = sqlx::query_scalar::<_, i64>(&format!(
+                "SELECT COUNT(*) FROM pgmq.\"q_{dead_letter_queue}\" WHERE msg_id = ANY($1)"
+            ))
+            .bind(&msg_ids)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_| QueueErrors::DatabaseError)?;
+            let _ = tx.rollback().await;
+
+            return Err(if existing as usize == msg_ids.len() {
+                QueueErrors::MessageLocked
+            } else {
+                QueueErrors::MessageNotFound
+            });
+        }
+
+        let mut requeued = Vec::with_capacity(messages.len());
+        for message in messages {
+            let new_id = sqlx::query_scalar::<_, i64>("SELECT * FROM pgmq.send($1, $2)")
+                .bind(queue)
+                .bind(&message.message)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|_| QueueErrors::DatabaseError)?;
+
+            sqlx::query("SELECT pgmq.delete($1, $2)")
+                .bind(&dead_letter_queue)
+                .bind(message.msg_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|_| QueueErrors::DatabaseError)?;
+
+            requeued.push(new_id);
+        }
+
+        tx.commit().await.map_err(|_| QueueErrors::DatabaseError)?;
+
+        Ok(requeued)
+    }
+
+    /// Reads a message as stored by pgmq, without decoding its payload.
+    async fn raw_message(
+        state: &AppState,
+        queue: &str,
+        msg_id: i64,
+    ) -> Result<serde_json::Value, QueueErrors> {
+        sqlx::query_scalar::<_, serde_json::Value>(&format!(
+            "SELECT message FROM pgmq.\"q_{queue}\" WHERE msg_id = $1"
+        ))
+        .bind(msg_id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?
+        .ok_or(QueueErrors::MessageNotFound)
+    }
+
+    async fn decode_messages(
+        state: &AppState,
+        messages: Vec<QueueMessage>,
+    ) -> Result<Vec<QueueMessage>, QueueErrors> {
+        let mut decoded = Vec::with_capacity(messages.len());
+        for mut message in messages {
+            message.message = payload::decode(state, message.message).await?;
+            decoded.push(message);
+        }
+        Ok(decoded)
+    }
+
+    async fn ensure_exists(state: &AppState, queue: &str) -> Result<(), QueueErrors> {
+        Self::validate_queue_name(queue)?;
+
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM pgmq.list_queues() WHERE queue_name = $1)",
+        )
+        .bind(queue)
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| QueueErrors::DatabaseError)?;
+
+        if !exists {
+            return Err(QueueErrors::QueueNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Queue names end up in table identifiers, which pgmq lowercases, so only `[a-z0-9_]`
+    /// is accepted. Names are kept short enough for their dead letter queue, a dead letter
+    /// queue name is valid when the name of its queue is.
+    fn validate_queue_name(queue: &str) -> Result<(), QueueErrors> {
+        let base = queue.strip_suffix(DEAD_LETTER_SUFFIX).unwrap_or(queue);
+        let is_valid = !base.is_empty()
+            && base.len() <= MAX_QUEUE_NAME_LENGTH - DEAD_LETTER_SUFFIX.len()
+            && queue
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+        if !is_valid {
+            return Err(QueueErrors::InvalidQueueName);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEAD_LETTER_SUFFIX, MAX_QUEUE_NAME_LENGTH, QueueService};
+
+    #[test]
+    fn validate_queue_name_accepts_lowercase_identifiers() {
+        assert!(QueueService::validate_queue_name("orders").is_ok());
+        assert!(QueueService::validate_queue_name("orders_v2_dlq").is_ok());
+    }
+
+    #[test]
+    fn validate_queue_name_rejects_names_pgmq_would_change() {
+        assert!(QueueService::validate_queue_name("").is_err());
+        assert!(QueueService::validate_queue_name("Orders").is_err());
+        assert!(QueueService::validate_queue_name("orders-dlq").is_err());
+        assert!(QueueService::validate_queue_name("orders\"; DROP").is_err());
+        assert!(QueueService::validate_queue_name(&"q".repeat(48)).is_err());
+    }
+
+    #[test]
+    fn validate_queue_name_leaves_room_for_the_dead_letter_suffix() {
+        let longest = "q".repeat(MAX_QUEUE_NAME_LENGTH - DEAD_LETTER_SUFFIX.len());
+        let dead_letter_queue = QueueService::dead_letter_queue_name(&longest);
+
+        assert!(QueueService::validate_queue_name(&longest).is_ok());
+        assert!(QueueService::validate_queue_name(&dead_letter_queue).is_ok());
+        assert_eq!(dead_letter_queue.len(), MAX_QUEUE_NAME_LENGTH);
+        assert!(QueueService::validate_queue_name(&format!("{longest}q")).is_err());
+        assert!(QueueService::validate_queue_name(&format!("{dead_letter_queue}_dlq")).is_err());
+        assert!(QueueService::validate_queue_name(DEAD_LETTER_SUFFIX).is_err());
+    }
+}
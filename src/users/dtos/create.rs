@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use uuid::Uuid;
 use validator::Validate;
 
@@ -7,12 +8,21 @@ pub struct AuthUser {
     #[validate(email)]
     #[validate(length(min = 5, max = 100))]
     pub email: String,
-    #[validate(length(min = 8))]
     pub password: String,
 }
 
 #[derive(Debug, Validate, Deserialize)]
-#[allow(dead_code)]
+pub struct RegisterUser {
+    #[validate(email)]
+    #[validate(length(min = 5, max = 100))]
+    pub email: String,
+    pub password: String,
+    /// Generated from the email when omitted
+    #[validate(length(min = 3, max = 50))]
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
 pub struct CreateUser {
     #[validate(length(min = 3, max = 50))]
     pub username: String,
@@ -21,17 +31,18 @@ pub struct CreateUser {
     pub email: String,
     #[validate(length(min = 3, max = 100))]
     pub full_name: Option<String>,
-    #[validate(length(min = 8))]
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
-#[allow(dead_code)]
+#[derive(Debug, Serialize, FromRow)]
 pub struct UserResponse {
     pub id: Uuid,
     pub username: String,
     pub email: String,
     pub full_name: Option<String>,
+    pub is_active: bool,
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub roles: Vec<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct MfaCodeRequest {
+    /// Six digit TOTP code, or a recovery code where accepted
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct DisableMfaRequest {
+    pub password: String,
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleMfaRequest {
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaSetupResponse {
+    /// Base32 secret, for authenticator apps that cannot scan the URI
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Response of `/login` for users with two-factor authentication enabled.
+#[derive(Debug, Serialize)]
+pub struct MfaChallenge {
+    pub mfa_token: String,
+    pub token_type: String,
+    /// Lifetime of the mfa token in seconds
+    pub expires_in: i64,
+}
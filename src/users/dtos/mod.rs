@@ -1,2 +1,14 @@
 mod create;
+mod list;
+mod mfa;
+mod password;
+mod role;
+mod token;
+mod update;
 pub use create::*;
+pub use list::*;
+pub use mfa::*;
+pub use password::*;
+pub use role::*;
+pub use token::*;
+pub use update::*;
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct AssignRoleRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub role: String,
+}
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct RefreshTokenRequest {
+    #[validate(length(min = 32, max = 255))]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct LogoutRequest {
+    #[validate(length(min = 32, max = 255))]
+    pub refresh_token: Option<String>,
+}
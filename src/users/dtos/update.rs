@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct UpdateUserRequest {
+    #[validate(length(min = 3, max = 50))]
+    pub username: Option<String>,
+    #[validate(length(min = 3, max = 100))]
+    pub full_name: Option<String>,
+}
@@ -0,0 +1,11 @@
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Role {
+    pub id: Uuid,
+    pub name: String,
+    pub mfa_required: bool,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
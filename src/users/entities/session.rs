@@ -0,0 +1,56 @@
+use std::future::{Ready, ready};
+
+use actix_web::{Error, FromRequest, HttpRequest, dev::Payload, http::header::USER_AGENT};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::helpers::client_ip::client_ip;
+
+/// Longest user agent stored with a session, longer ones are cut.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    /// Whether the session is the one of the token that made the request
+    #[sqlx(skip)]
+    pub current: bool,
+}
+
+/// Device a login is made from, recorded with its session.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub user_agent: Option<String>,
+    pub ip: String,
+}
+
+impl DeviceInfo {
+    pub fn of(req: &HttpRequest) -> Self {
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        DeviceInfo {
+            user_agent,
+            ip: client_ip(req),
+        }
+    }
+}
+
+impl FromRequest for DeviceInfo {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
@@ -4,7 +4,6 @@ use uuid::Uuid;
 use validator::Validate;
 
 #[derive(Debug, Validate, Deserialize, Serialize, FromRow)]
-#[allow(dead_code)]
 pub struct FullUser {
     pub id: Uuid,
     #[validate(length(min = 3, max = 50))]
@@ -14,7 +13,10 @@ pub struct FullUser {
     pub email: String,
     #[validate(length(min = 3, max = 100))]
     pub full_name: Option<String>,
+    #[serde(skip_serializing)]
     pub password_hash: String,
+    pub is_active: bool,
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -23,14 +25,17 @@ pub struct FullUser {
 pub struct PartialUser {
     pub id: Uuid,
     pub email: String,
+    pub username: String,
     pub password_hash: String,
 }
 
 #[derive(Debug, FromRow)]
-pub struct UserWithRole {
+pub struct UserWithRoles {
     pub id: Uuid,
-    #[allow(dead_code)]
     pub email: String,
     pub password_hash: String,
-    pub role_name: String,
+    pub is_active: bool,
+    pub email_verified: bool,
+    pub mfa_enabled: bool,
+    pub roles: Vec<String>,
 }
@@ -0,0 +1,23 @@
+use crate::config::Config;
+
+/// How unverified email addresses are treated, set by `EMAIL_VERIFICATION_POLICY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailVerificationPolicy {
+    /// Unverified users have full access
+    Optional,
+    /// Unverified users can log in, but get no permissions until they verify their email
+    Restricted,
+    /// Unverified users cannot log in
+    Required,
+}
+
+impl EmailVerificationPolicy {
+    pub fn configured() -> Self {
+        match Config::from_env().email_verification_policy.as_str() {
+            "optional" => EmailVerificationPolicy::Optional,
+            "restricted" => EmailVerificationPolicy::Restricted,
+            "required" => EmailVerificationPolicy::Required,
+            _ => panic!("EMAIL_VERIFICATION_POLICY must be optional, restricted or required"),
+        }
+    }
+}
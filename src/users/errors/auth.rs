@@ -1,6 +1,8 @@
 use actix_failwrap::ErrorResponse;
 use thiserror::Error;
 
+use crate::helpers::validate_password::PasswordViolations;
+
 #[derive(Debug, ErrorResponse, Error)]
 pub enum AuthErrors {
     #[error("Invalid credentials")]
@@ -11,13 +13,26 @@ pub enum AuthErrors {
     #[status_code(400)]
     EmailAlreadyRegistered,
 
-    #[error(
-        "Password must be at least 8 characters long, contain at least one uppercase letter, and one special character"
-    )]
+    #[error("Username already taken")]
+    #[status_code(409)]
+    UsernameTaken,
+
+    #[error("Account deactivated")]
+    #[status_code(403)]
+    AccountDeactivated,
+
+    #[error("Email address not verified")]
+    #[status_code(403)]
+    EmailNotVerified,
+
+    #[error("Password does not meet the policy: {0}")]
     #[status_code(400)]
-    WeakPassword,
+    WeakPassword(PasswordViolations),
+
+    #[error("Current password is incorrect")]
+    #[status_code(400)]
+    IncorrectCurrentPassword,
 
-    #[allow(dead_code)]
     #[error("User not found")]
     #[status_code(404)]
     UserNotFound,
@@ -26,6 +41,34 @@ pub enum AuthErrors {
     #[status_code(500)]
     DefaultRoleNotFound,
 
+    #[error("Role not found")]
+    #[status_code(404)]
+    RoleNotFound,
+
+    #[error("User does not hold this role")]
+    #[status_code(404)]
+    RoleNotAssigned,
+
+    #[error("Two-factor authentication is already enabled")]
+    #[status_code(409)]
+    MfaAlreadyEnabled,
+
+    #[error("Two-factor authentication is not set up")]
+    #[status_code(400)]
+    MfaNotEnrolled,
+
+    #[error("Invalid two-factor authentication code")]
+    #[status_code(401)]
+    InvalidMfaCode,
+
+    #[error("Two-factor authentication is required by one of your roles")]
+    #[status_code(403)]
+    MfaRequired,
+
+    #[error("Access denied")]
+    #[status_code(403)]
+    Forbidden,
+
     #[error("Database transaction error")]
     #[status_code(500)]
     TransactionError,
@@ -34,11 +77,30 @@ pub enum AuthErrors {
     #[status_code(500)]
     PasswordHashError,
 
-    #[allow(dead_code)]
     #[error("Error generating token")]
     #[status_code(500)]
     TokenGenerationError,
 
+    #[error("Invalid request")]
+    #[status_code(400)]
+    InvalidRequest,
+
+    #[error("Invalid or expired token")]
+    #[status_code(401)]
+    InvalidToken,
+
+    #[error("Invalid or expired refresh token")]
+    #[status_code(401)]
+    InvalidRefreshToken,
+
+    #[error("Session not found")]
+    #[status_code(404)]
+    SessionNotFound,
+
+    #[error("Refresh token reuse detected, all sessions of this login were revoked")]
+    #[status_code(401)]
+    RefreshTokenReused,
+
     #[error("Database error")]
     #[status_code(500)]
     DatabaseError,
@@ -0,0 +1,37 @@
+use actix_web::{HttpResponse, http::header::RETRY_AFTER};
+use thiserror::Error;
+
+use crate::users::errors::auth::AuthErrors;
+
+/// Errors of routes that check passwords and are subject to lockouts.
+///
+/// `ErrorResponse` cannot attach headers computed from the error, so the `429` response
+/// carrying `Retry-After` is built by hand.
+#[derive(Debug, Error)]
+pub enum LockoutErrors {
+    #[error("Too many failed attempts, try again in {retry_after} seconds")]
+    Locked { retry_after: i64 },
+
+    #[error(transparent)]
+    Auth(#[from] AuthErrors),
+
+    /// Extractor failures, answered like the same [`AuthErrors`] by `#[error_override]`
+    #[error("{}", AuthErrors::InvalidToken)]
+    InvalidToken,
+
+    #[error("{}", AuthErrors::InvalidRequest)]
+    InvalidRequest,
+}
+
+impl From<LockoutErrors> for HttpResponse {
+    fn from(error: LockoutErrors) -> Self {
+        match error {
+            LockoutErrors::Locked { retry_after } => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after.to_string()))
+                .body(error.to_string()),
+            LockoutErrors::Auth(error) => error.into(),
+            LockoutErrors::InvalidToken => AuthErrors::InvalidToken.into(),
+            LockoutErrors::InvalidRequest => AuthErrors::InvalidRequest.into(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    config::Config,
+    mailer::MailerService,
+    users::errors::{auth::AuthErrors, lockout::LockoutErrors},
+};
+
+/// Failed password checks per account and per client IP.
+///
+/// Reaching `LOGIN_MAX_ATTEMPTS` (account) or `LOGIN_IP_MAX_ATTEMPTS` (IP) failures within
+/// `LOGIN_ATTEMPT_WINDOW_MINUTES` locks further attempts. Each lockout lasts twice as long
+/// as the previous one, from `LOGIN_LOCKOUT_BASE_SECONDS` up to `LOGIN_LOCKOUT_MAX_SECONDS`.
+pub struct LoginThrottle;
+
+impl LoginThrottle {
+    pub async fn check_ip(state: &AppState, ip: &str) -> Result<(), LockoutErrors> {
+        let retry_after = sqlx::query_scalar!(
+            r#"SELECT CEIL(EXTRACT(EPOCH FROM locked_until - NOW()))::BIGINT as "retry_after!"
+               FROM login_ip_attempts
+               WHERE ip = $1 AND locked_until > NOW()"#,
+            ip
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        match retry_after {
+            Some(retry_after) => Err(LockoutErrors::Locked { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn check_account(state: &AppState, user_id: Uuid) -> Result<(), LockoutErrors> {
+        let retry_after = sqlx::query_scalar!(
+            r#"SELECT CEIL(EXTRACT(EPOCH FROM locked_until - NOW()))::BIGINT as "retry_after!"
+               FROM users
+               WHERE id = $1 AND locked_until > NOW()"#,
+            user_id
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        match retry_after {
+            Some(retry_after) => Err(LockoutErrors::Locked { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a wrong password from `ip`, against `account` when the email matched a user.
+    ///
+    /// Returns [`LockoutErrors::Locked`] when this failure triggered a lockout. The owner of
+    /// a locked account is notified by email, sent in the background.
+    pub async fn record_failure(
+        state: &AppState,
+        account: Option<(Uuid, &str)>,
+        ip: &str,
+    ) -> Result<(), LockoutErrors> {
+        let config = Config::from_env();
+        let mut locked_for = None;
+
+        let ip_attempts = sqlx::query!(
+            "INSERT INTO login_ip_attempts (ip, failed_attempts, last_failed_at)
+             VALUES ($1, 1, NOW())
+             ON CONFLICT (ip) DO UPDATE SET
+                 lockout_count = CASE
+                     WHEN login_ip_attempts.last_failed_at < NOW() - make_interval(hours => $3)
+                     THEN 0 ELSE login_ip_attempts.lockout_count END,
+                 failed_attempts = CASE
+                     WHEN login_ip_attempts.last_failed_at < NOW() - make_interval(mins => $2)
+                     THEN 1 ELSE login_ip_attempts.failed_attempts + 1 END,
+                 last_failed_at = NOW()
+             RETURNING failed_attempts, lockout_count",
+            ip,
+            config.login_attempt_window_minutes,
+            config.login_lockout_reset_hours
+        )
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        if ip_attempts.failed_attempts >= config.login_ip_max_attempts {
+            let seconds = Self::lockout_seconds(&config, ip_attempts.lockout_count);
+            sqlx::query!(
+                "UPDATE login_ip_attempts
+                 SET locked_until = NOW() + make_interval(secs => $2),
+                     lockout_count = lockout_count + 1,
+                     failed_attempts = 0
+                 WHERE ip = $1",
+                ip,
+                seconds as f64
+            )
+            .execute(&state.db_pool)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+            locked_for = Some(seconds);
+        }
+
+        if let Some((user_id, email)) = account {
+            let attempts = sqlx::query!(
+                "UPDATE users SET
+                     lockout_count = CASE
+                         WHEN last_failed_login_at < NOW() - make_interval(hours => $3)
+                         THEN 0 ELSE lockout_count END,
+                     failed_login_attempts = CASE
+                         WHEN last_failed_login_at IS NULL
+                           OR last_failed_login_at < NOW() - make_interval(mins => $2)
+                         THEN 1 ELSE failed_login_attempts + 1 END,
+                     last_failed_login_at = NOW()
+                 WHERE id = $1
+                 RETURNING failed_login_attempts, lockout_count",
+                user_id,
+                config.login_attempt_window_minutes,
+                config.login_lockout_reset_hours
+            )
+            .fetch_one(&state.db_pool)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+            if attempts.failed_login_attempts >= config.login_max_attempts {
+                let seconds = Self::lockout_seconds(&config, attempts.lockout_count);
+                sqlx::query!(
+                    "UPDATE users
+                     SET locked_until = NOW() + make_interval(secs => $2),
+                         lockout_count = lockout_count + 1,
+                         failed_login_attempts = 0
+                     WHERE id = $1",
+                    user_id,
+                    seconds as f64
+                )
+                .execute(&state.db_pool)
+                .await
+                .map_err(|_| AuthErrors::DatabaseError)?;
+
+                let email = email.to_string();
+                actix_web::rt::spawn(async move {
+                    if let Err(e) = MailerService::send_account_locked_email(&email, seconds).await
+                    {
+                        eprintln!("Failed to send account locked email: {e}");
+                    }
+                });
+                locked_for = Some(locked_for.unwrap_or(0).max(seconds));
+            }
+        }
+
+        sqlx::query!(
+            "DELETE FROM login_ip_attempts
+             WHERE last_failed_at < NOW() - make_interval(hours => $1)
+               AND (locked_until IS NULL OR locked_until < NOW())",
+            config.login_lockout_reset_hours
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        match locked_for {
+            Some(retry_after) => Err(LockoutErrors::Locked { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Clears the failures of an account after its password was verified.
+    ///
+    /// IP failures are kept, otherwise logging into an own account would reset them.
+    pub async fn record_success(state: &AppState, user_id: Uuid) -> Result<(), LockoutErrors> {
+        sqlx::query!(
+            "UPDATE users
+             SET failed_login_attempts = 0, lockout_count = 0, locked_until = NULL
+             WHERE id = $1 AND (failed_login_attempts > 0 OR lockout_count > 0)",
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    fn lockout_seconds(config: &Config, previous_lockouts: i32) -> i64 {
+        let factor = 1_i64 << previous_lockouts.clamp(0, 30);
+        config
+            .login_lockout_base_seconds
+            .saturating_mul(factor)
+            .min(config.login_lockout_max_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(base: i64, max: i64) -> Config {
+        Config {
+            login_lockout_base_seconds: base,
+            login_lockout_max_seconds: max,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_with_each_previous_lockout() {
+        let config = config(60, 3600);
+        assert_eq!(LoginThrottle::lockout_seconds(&config, 0), 60);
+        assert_eq!(LoginThrottle::lockout_seconds(&config, 1), 120);
+        assert_eq!(LoginThrottle::lockout_seconds(&config, 3), 480);
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let config = config(60, 3600);
+        assert_eq!(LoginThrottle::lockout_seconds(&config, 6), 3600);
+        assert_eq!(LoginThrottle::lockout_seconds(&config, 30), 3600);
+    }
+
+    #[test]
+    fn lockout_handles_out_of_range_counts() {
+        let uncapped = config(60, i64::MAX);
+        assert_eq!(LoginThrottle::lockout_seconds(&uncapped, -5), 60);
+        // The exponent is clamped and the multiplication saturates instead of overflowing
+        assert_eq!(
+            LoginThrottle::lockout_seconds(&uncapped, i32::MAX),
+            60 * (1 << 30)
+        );
+        let huge_base = config(i64::MAX / 2, i64::MAX);
+        assert_eq!(LoginThrottle::lockout_seconds(&huge_base, 30), i64::MAX);
+    }
+}
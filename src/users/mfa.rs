@@ -0,0 +1,286 @@
+use chrono::Utc;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    config::Config,
+    helpers::token::{generate_opaque_token, hash_token},
+    middlewares::jwt::generate_token,
+    users::{MfaChallenge, MfaSetupResponse, errors::auth::AuthErrors},
+};
+
+/// Recovery codes handed out on enrollment, each usable once.
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Seconds covered by one TOTP code.
+const TOTP_STEP: u64 = 30;
+
+/// TOTP two-factor authentication.
+///
+/// Enrollment stores a pending secret that only takes effect once a code generated from
+/// it is confirmed. Codes are accepted one step either side of the current one, and never
+/// twice, since the last accepted step is stored with the user.
+pub struct MfaService;
+
+impl MfaService {
+    /// Generates a new pending secret, replacing any unconfirmed one.
+    pub async fn begin_enrollment(
+        state: &AppState,
+        user_id: Uuid,
+        email: &str,
+    ) -> Result<MfaSetupResponse, AuthErrors> {
+        let secret = Secret::generate_secret().to_encoded().to_string();
+        let totp = Self::totp(&secret, email)?;
+
+        let result = sqlx::query!(
+            "UPDATE users SET mfa_secret = $2, mfa_last_used_step = NULL
+             WHERE id = $1 AND mfa_enabled_at IS NULL",
+            user_id,
+            secret
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthErrors::MfaAlreadyEnabled);
+        }
+
+        Ok(MfaSetupResponse {
+            otpauth_uri: totp.get_url(),
+            secret,
+        })
+    }
+
+    /// Enables two-factor authentication once `code` matches the pending secret.
+    ///
+    /// Returns the recovery codes, which are only stored hashed.
+    pub async fn confirm_enrollment(
+        state: &AppState,
+        user_id: Uuid,
+        email: &str,
+        code: &str,
+    ) -> Result<Vec<String>, AuthErrors> {
+        let record = sqlx::query!(
+            "SELECT mfa_secret, mfa_enabled_at FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)?;
+
+        if record.mfa_enabled_at.is_some() {
+            return Err(AuthErrors::MfaAlreadyEnabled);
+        }
+        let secret = record.mfa_secret.ok_or(AuthErrors::MfaNotEnrolled)?;
+
+        if !Self::accept_totp(state, user_id, &secret, email, code.trim()).await? {
+            return Err(AuthErrors::InvalidMfaCode);
+        }
+
+        sqlx::query!(
+            "UPDATE users SET mfa_enabled_at = NOW() WHERE id = $1",
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Self::regenerate_recovery_codes(state, user_id).await
+    }
+
+    /// Checks a TOTP or recovery code of a user with two-factor authentication enabled.
+    pub async fn verify(
+        state: &AppState,
+        user_id: Uuid,
+        email: &str,
+        code: &str,
+    ) -> Result<bool, AuthErrors> {
+        let secret = sqlx::query_scalar!(
+            "SELECT mfa_secret FROM users WHERE id = $1 AND mfa_enabled_at IS NOT NULL",
+            user_id
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .flatten()
+        .ok_or(AuthErrors::MfaNotEnrolled)?;
+
+        let code = code.trim();
+        if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+            return Self::accept_totp(state, user_id, &secret, email, code).await;
+        }
+
+        let used = sqlx::query!(
+            "UPDATE mfa_recovery_codes SET used_at = NOW()
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            user_id,
+            hash_token(&Self::normalize_recovery_code(code))
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(used.rows_affected() == 1)
+    }
+
+    /// Replaces every recovery code of a user and returns the new ones.
+    pub async fn regenerate_recovery_codes(
+        state: &AppState,
+        user_id: Uuid,
+    ) -> Result<Vec<String>, AuthErrors> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let token = generate_opaque_token();
+                format!("{}-{}", &token[..5], &token[5..10])
+            })
+            .collect();
+        let hashes: Vec<String> = codes
+            .iter()
+            .map(|code| hash_token(&Self::normalize_recovery_code(code)))
+            .collect();
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        sqlx::query!("DELETE FROM mfa_recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "INSERT INTO mfa_recovery_codes (user_id, code_hash)
+             SELECT $1, UNNEST($2::VARCHAR[])",
+            user_id,
+            &hashes
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        tx.commit()
+            .await
+            .map_err(|_| AuthErrors::TransactionError)?;
+
+        Ok(codes)
+    }
+
+    /// Turns two-factor authentication off and drops the recovery codes.
+    pub async fn disable(state: &AppState, user_id: Uuid) -> Result<(), AuthErrors> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "UPDATE users
+             SET mfa_secret = NULL, mfa_enabled_at = NULL, mfa_last_used_step = NULL
+             WHERE id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        sqlx::query!("DELETE FROM mfa_recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| AuthErrors::TransactionError)
+    }
+
+    /// Whether any role of the user requires two-factor authentication.
+    pub async fn required_for(state: &AppState, user_id: Uuid) -> Result<bool, AuthErrors> {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                   SELECT 1 FROM users_role ur
+                   JOIN catalogs.roles r ON ur.role_id = r.id
+                   WHERE ur.user_id = $1 AND r.mfa_required
+               ) as "required!""#,
+            user_id
+        )
+        .fetch_one(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)
+    }
+
+    /// Short-lived token exchanged at `/login/mfa` for the full token pair.
+    pub fn challenge(user_id: Uuid) -> MfaChallenge {
+        let ttl_minutes = Config::from_env().mfa_token_ttl_minutes;
+        MfaChallenge {
+            mfa_token: generate_token(
+                "PGMQ-Backend".to_string(),
+                ttl_minutes,
+                "mfa_pending".to_owned(),
+                user_id,
+                Vec::new(),
+                None,
+            ),
+            token_type: "mfa_pending".to_string(),
+            expires_in: ttl_minutes * 60,
+        }
+    }
+
+    /// Accepts a TOTP code from the previous, current or next step, at most once.
+    async fn accept_totp(
+        state: &AppState,
+        user_id: Uuid,
+        secret: &str,
+        email: &str,
+        code: &str,
+    ) -> Result<bool, AuthErrors> {
+        let totp = Self::totp(secret, email)?;
+        let now = Utc::now().timestamp() as u64;
+        let Some(step) = [now - TOTP_STEP, now, now + TOTP_STEP]
+            .into_iter()
+            .find(|time| totp.check(code, *time))
+            .map(|time| (time / TOTP_STEP) as i64)
+        else {
+            return Ok(false);
+        };
+
+        let result = sqlx::query!(
+            "UPDATE users SET mfa_last_used_step = $2
+             WHERE id = $1 AND (mfa_last_used_step IS NULL OR mfa_last_used_step < $2)",
+            user_id,
+            step
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    fn totp(secret: &str, email: &str) -> Result<TOTP, AuthErrors> {
+        let secret = Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .map_err(|_| AuthErrors::TokenGenerationError)?;
+
+        // No skew: `check` then compares one step in constant time, `accept_totp` picks
+        // the steps tried so it knows which one matched
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            0,
+            TOTP_STEP,
+            secret,
+            Some(Config::from_env().mfa_issuer),
+            email.to_string(),
+        )
+        .map_err(|_| AuthErrors::TokenGenerationError)
+    }
+
+    fn normalize_recovery_code(code: &str) -> String {
+        code.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase()
+    }
+}
@@ -1,6 +1,14 @@
 pub mod entities {
+    mod refresh_token;
+    mod role;
+    mod session;
     mod user;
+    mod verification;
+    pub use refresh_token::*;
+    pub use role::*;
+    pub use session::*;
     pub use user::*;
+    pub use verification::*;
 }
 
 mod dtos;
@@ -8,7 +16,17 @@ pub use dtos::*;
 
 pub mod errors;
 
+pub mod permissions;
+
+mod lockout;
+pub use lockout::LoginThrottle;
+
+mod sessions;
+
+mod mfa;
+pub use mfa::MfaService;
+
 mod routes;
-pub use routes::config as routes;
+pub use routes::{admin_config as admin_routes, api_config as api_routes, config as routes};
 
 mod service;
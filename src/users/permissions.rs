@@ -0,0 +1,14 @@
+//! Permission names seeded in `catalogs.permissions`.
+//!
+//! The JWT validator attaches the permissions granted to the user's roles as
+//! authorities, so routes guard on these instead of role names.
+
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+pub const USERS_DELETE: &str = "users:delete";
+pub const ROLES_ASSIGN: &str = "roles:assign";
+pub const ROLES_WRITE: &str = "roles:write";
+pub const API_KEYS_MANAGE: &str = "api_keys:manage";
+pub const AUDIT_READ: &str = "audit:read";
+pub const WORKFLOWS_READ: &str = "workflows:read";
+pub const WORKFLOWS_WRITE: &str = "workflows:write";
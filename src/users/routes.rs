@@ -1,41 +1,169 @@
 use actix_failwrap::proof_route;
 use actix_web::{
     HttpResponse, Result,
-    web::{self, Data, Json},
+    http::header::CACHE_CONTROL,
+    web::{self, Data, Json, Path, Query},
 };
+use actix_web_grants::authorities::{AuthDetails, AuthoritiesCheck};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
 use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
 
 use crate::{
     AppState,
-    helpers::hash_password::verify_password,
-    helpers::validate_password::is_valid_password,
-    mailer::errors::MailerErrors,
-    mailer::{ForgotPasswordRequest, ForgotPasswordResponse, MailerService, ResetPasswordRequest},
-    users::dtos::AuthUser,
-    users::entities::PartialUser,
-    users::errors::auth::AuthErrors,
+    audit::{
+        AuditLog, actions,
+        entities::{AuditActor, AuditEvent},
+    },
+    helpers::hash_password::{PasswordHasher, verify_password},
+    helpers::validate_password::validate_password,
+    mailer::{
+        ForgotPasswordRequest, ForgotPasswordResponse, MagicLinkRequest, MailerService,
+        ResendVerificationRequest, ResetPasswordRequest, VerifyEmailRequest,
+        VerifyMagicLinkRequest,
+    },
+    middlewares::{
+        jwt::{Claims, validate_token},
+        signing_keys::SigningKeys,
+    },
+    users::dtos::{
+        AssignRoleRequest, AuthUser, ChangePasswordRequest, CreateUser, DisableMfaRequest,
+        ListUsersQuery, LogoutRequest, MfaCodeRequest, MfaLoginRequest, RecoveryCodesResponse,
+        RefreshTokenRequest, RegisterUser, RoleMfaRequest, UpdateUserRequest, UserResponse,
+    },
+    users::entities::{
+        DeviceInfo, EmailVerificationPolicy, FullUser, PartialUser, RefreshToken, Role, Session,
+    },
+    users::errors::{auth::AuthErrors, lockout::LockoutErrors},
+    users::permissions::{ROLES_ASSIGN, ROLES_WRITE, USERS_DELETE, USERS_READ, USERS_WRITE},
+    users::{LoginThrottle, MfaService},
 };
 
 /// Configure user routes
 ///
 /// `POST` `/register` - Register a new user
 ///
+/// Without a username one is generated from the email, e.g. `alice` or `alice_3f9a1c`.
+/// Email and username conflicts are reported separately.
+/// Register User entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct RegisterUser {
+///     #[validate(email)]
+///     #[validate(length(min = 5, max = 100))]
+///     pub email: String,
+///     pub password: String,
+///     #[validate(length(min = 3, max = 50))]
+///     pub username: Option<String>,
+/// }
+/// ```
+///
 /// `POST` `/login` - Login an existing user
+///
+/// Repeated failures lock the account and the client IP for a growing period, during which
+/// the route responds `429` with a `Retry-After` header (see `LoginThrottle`).
 /// Auth User entity:
-/// ```no_run
+/// ```ignore
 /// #[derive(Debug, Validate, Deserialize)]
 /// pub struct AuthUser {
 ///     #[validate(email)]
 ///     #[validate(length(min = 5, max = 100))]
 ///     pub email: String,
-///     #[validate(length(min = 8))]
 ///     pub password: String,
 /// }
 /// ```
+/// `POST` `/register` and `/login` respond with an access token and a refresh token:
+/// ```ignore
+/// #[derive(Serialize, Deserialize, Debug)]
+/// pub struct AuthTokens {
+///     pub access_token: String,
+///     pub refresh_token: String,
+///     pub token_type: String,
+///     pub expires_in: i64,
+/// }
+/// ```
+///
+/// Users with two-factor authentication enabled get an `mfa_pending` token from `/login`
+/// instead, which only `/login/mfa` accepts:
+/// ```ignore
+/// #[derive(Debug, Serialize)]
+/// pub struct MfaChallenge {
+///     pub mfa_token: String,
+///     pub token_type: String,
+///     pub expires_in: i64,
+/// }
+/// ```
+///
+/// `POST` `/login/mfa` - Exchange an `mfa_pending` token and a TOTP or recovery code for tokens
+///
+/// Wrong codes count towards the account and IP lockout of `/login`.
+/// MFA Login Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct MfaLoginRequest {
+///     pub mfa_token: String,
+///     #[validate(length(min = 6, max = 32))]
+///     pub code: String,
+/// }
+/// ```
+///
+/// `POST` `/login/magic-link` - Email a one-time sign-in link, when `MAGIC_LINK_ENABLED` is set
+///
+/// Responds the same whether or not the email is registered. Magic Link Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct MagicLinkRequest {
+///     #[validate(email)]
+///     #[validate(length(min = 5, max = 100))]
+///     pub email: String,
+/// }
+/// ```
+///
+/// `POST` `/login/magic-link/verify` - Exchange a sign-in link token for tokens
+///
+/// Responds like `/login`, including the `mfa_pending` token for users with two-factor
+/// authentication. The email address is marked verified. Verify Magic Link Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct VerifyMagicLinkRequest {
+///     #[validate(length(min = 32, max = 255))]
+///     pub token: String,
+/// }
+/// ```
+///
+/// `POST` `/token/refresh` - Exchange a refresh token for a new token pair
+///
+/// Refresh tokens are single use, reusing a rotated one revokes every token of that login.
+/// Refresh Token Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct RefreshTokenRequest {
+///     #[validate(length(min = 32, max = 255))]
+///     pub refresh_token: String,
+/// }
+/// ```
+///
+/// `POST` `/logout` - Revoke the access token sent in the `Authorization` header
+///
+/// When the optional body carries the refresh token, its whole token family is revoked too.
+/// Logout Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct LogoutRequest {
+///     #[validate(length(min = 32, max = 255))]
+///     pub refresh_token: Option<String>,
+/// }
+/// ```
+///
+/// `POST` `/logout/all` - Revoke every access and refresh token of the current user
+///
 /// `POST` `/forgot-password` - Request password reset email
 ///
+/// Responds the same whether or not the email is registered. Sending a new link invalidates
+/// the previous ones.
 /// Forgot Password Request entity:
-/// ```no_run
+/// ```ignore
 /// #[derive(Debug, Validate, Deserialize)]
 /// pub struct ForgotPasswordRequest {
 ///     #[validate(email)]
@@ -46,57 +174,986 @@ use crate::{
 ///
 /// `POST` `/reset-password` - Reset password with token
 ///
+/// A token works once, and a successful reset invalidates every other link and session.
 /// Reset Password Request entity:
-/// ```no_run
+/// ```ignore
 /// #[derive(Debug, Validate, Deserialize)]
 /// pub struct ResetPasswordRequest {
 ///     #[validate(length(min = 32, max = 255))]
 ///     pub token: String,
-///     #[validate(length(min = 8))]
 ///     pub new_password: String,
 /// }
 /// ```
+///
+/// `POST` `/verify-email` - Confirm an email address with the token sent on registration
+///
+/// Verify Email Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct VerifyEmailRequest {
+///     #[validate(length(min = 32, max = 255))]
+///     pub token: String,
+/// }
+/// ```
+///
+/// `POST` `/resend-verification` - Send a new verification email, invalidating older links
+///
+/// Always answers `200` with the same message, whether or not the email is registered.
+///
+/// Resend Verification Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct ResendVerificationRequest {
+///     #[validate(email)]
+///     #[validate(length(min = 5, max = 100))]
+///     pub email: String,
+/// }
+/// ```
+///
+/// `GET` `/.well-known/jwks.json` - Public keys verifying our tokens, empty with `HS512`
+///
+/// Depending on `EMAIL_VERIFICATION_POLICY`, `/register` may respond `201` with a message
+/// instead of tokens, and unverified users may be refused at `/login`.
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(register_user)
         .service(login_user)
+        .service(login_mfa)
+        .service(request_magic_link)
+        .service(verify_magic_link)
+        .service(refresh_token)
+        .service(logout)
+        .service(logout_all)
         .service(forgot_password)
-        .service(reset_password);
+        .service(reset_password)
+        .service(verify_email)
+        .service(resend_verification)
+        .service(jwks);
+}
+
+/// Configure authenticated user routes, mounted under the `/api` scope
+///
+/// `GET` `/api/me` - Get the current user with its roles
+///
+/// User Response entity:
+/// ```ignore
+/// #[derive(Debug, Serialize, FromRow)]
+/// pub struct UserResponse {
+///     pub id: Uuid,
+///     pub username: String,
+///     pub email: String,
+///     pub full_name: Option<String>,
+///     pub is_active: bool,
+///     pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+///     pub roles: Vec<String>,
+///     pub created_at: chrono::DateTime<chrono::Utc>,
+///     pub updated_at: chrono::DateTime<chrono::Utc>,
+/// }
+/// ```
+///
+/// `POST` `/api/me/password` - Change the password of the current user
+///
+/// Every other session is signed out, the response carries a new token pair and a
+/// notification is emailed. Wrong current passwords count towards the account lockout
+/// and a locked account gets `429` with a `Retry-After` header.
+/// Change Password Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct ChangePasswordRequest {
+///     pub current_password: String,
+///     pub new_password: String,
+/// }
+/// ```
+///
+/// `GET` `/api/me/profile` - Get the profile of the current user
+///
+/// `PATCH` `/api/me/profile` - Update the username and/or full name of the current user
+///
+/// Fields follow the `FullUser` validation rules, a username held by another user is
+/// rejected with `409`. Update Profile Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct UpdateUserRequest {
+///     #[validate(length(min = 3, max = 50))]
+///     pub username: Option<String>,
+///     #[validate(length(min = 3, max = 100))]
+///     pub full_name: Option<String>,
+/// }
+/// ```
+///
+/// `POST` `/api/me/mfa/setup` - Start TOTP enrollment
+///
+/// Responds with `{ secret, otpauth_uri }`, nothing changes until the first code is confirmed.
+///
+/// `POST` `/api/me/mfa/confirm` - Enable two-factor authentication with a code from the new secret
+///
+/// Responds with `{ recovery_codes }`, shown only this once. MFA Code Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct MfaCodeRequest {
+///     #[validate(length(min = 6, max = 32))]
+///     pub code: String,
+/// }
+/// ```
+///
+/// `POST` `/api/me/mfa/recovery-codes` - Replace the recovery codes, takes a current `MfaCodeRequest`
+///
+/// `POST` `/api/me/mfa/disable` - Turn two-factor authentication off
+///
+/// Refused with `403` while a role of the user requires it. Disable MFA Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct DisableMfaRequest {
+///     pub password: String,
+///     #[validate(length(min = 6, max = 32))]
+///     pub code: String,
+/// }
+/// ```
+///
+/// `GET` `/api/me/sessions` - List the signed in sessions of the current user
+///
+/// Every login starts a session, which lasts as long as its refresh tokens. `current`
+/// marks the session of the token making the request. Session entity:
+/// ```ignore
+/// #[derive(Debug, Serialize, FromRow)]
+/// pub struct Session {
+///     pub id: Uuid,
+///     pub user_agent: Option<String>,
+///     pub ip: Option<String>,
+///     pub created_at: chrono::DateTime<chrono::Utc>,
+///     pub last_seen_at: chrono::DateTime<chrono::Utc>,
+///     #[sqlx(skip)]
+///     pub current: bool,
+/// }
+/// ```
+///
+/// `DELETE` `/api/me/sessions/{id}` - Sign a session out, revoking its refresh and access tokens
+pub fn api_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(me)
+        .service(change_password)
+        .service(get_profile)
+        .service(update_profile)
+        .service(setup_mfa)
+        .service(confirm_mfa)
+        .service(regenerate_recovery_codes)
+        .service(disable_mfa)
+        .service(list_sessions)
+        .service(revoke_session);
+}
+
+#[proof_route("GET /me")]
+async fn me(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+) -> Result<HttpResponse, AuthErrors> {
+    let user = UserResponse::find_by_id(&state, claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Configure user admin routes, mounted under the authenticated `/admin` scope
+///
+/// `GET` `/admin/users` - List users, requires `users:read`
+///
+/// Query parameters: `email` (contains), `role`, `active`, `created_from` and `created_to`
+/// (RFC 3339), `sort` (`created_at`, `email` or `username`), `order` (`asc` or `desc`),
+/// `limit` (1-100, default 50) and `offset`. Responds with `{ users, total, limit, offset }`.
+///
+/// `POST` `/admin/users` - Create a user with the `user` role, requires `users:write`
+///
+/// Create User entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct CreateUser {
+///     #[validate(length(min = 3, max = 50))]
+///     pub username: String,
+///     #[validate(email)]
+///     #[validate(length(min = 5, max = 100))]
+///     pub email: String,
+///     #[validate(length(min = 3, max = 100))]
+///     pub full_name: Option<String>,
+///     pub password: String,
+/// }
+/// ```
+///
+/// `GET` `/admin/users/{id}` - Get a user, requires `users:read`
+///
+/// `PATCH` `/admin/users/{id}` - Update the username and/or full name, requires `users:write`
+///
+/// Update User Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct UpdateUserRequest {
+///     #[validate(length(min = 3, max = 50))]
+///     pub username: Option<String>,
+///     #[validate(length(min = 3, max = 100))]
+///     pub full_name: Option<String>,
+/// }
+/// ```
+///
+/// `POST` `/admin/users/{id}/deactivate` - Block logins and revoke every token, requires `users:write`
+///
+/// `POST` `/admin/users/{id}/activate` - Allow a deactivated user to log in again, requires `users:write`
+///
+/// `DELETE` `/admin/users/{id}` - Delete a user, requires `users:delete`
+///
+/// Role routes require the `roles:assign` permission and respond with the updated user.
+///
+/// `POST` `/admin/users/{id}/roles` - Grant a role to a user
+///
+/// Assign Role Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct AssignRoleRequest {
+///     #[validate(length(min = 1, max = 50))]
+///     pub role: String,
+/// }
+/// ```
+///
+/// `DELETE` `/admin/users/{id}/roles/{role}` - Take a role away from a user
+///
+/// `PUT` `/admin/roles/{name}/mfa` - Require two-factor authentication from holders of a role,
+/// requires `roles:write`
+///
+/// Holders without two-factor authentication keep no permissions until they enroll.
+/// Role MFA Request entity:
+/// ```ignore
+/// #[derive(Debug, Deserialize)]
+/// pub struct RoleMfaRequest {
+///     pub required: bool,
+/// }
+/// ```
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_users)
+        .service(create_user)
+        .service(get_user)
+        .service(update_user)
+        .service(deactivate_user)
+        .service(activate_user)
+        .service(delete_user)
+        .service(add_user_role)
+        .service(remove_user_role)
+        .service(set_role_mfa);
+}
+
+#[proof_route("GET /users")]
+async fn list_users(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    #[error_override(InvalidRequest)] query: Query<ListUsersQuery>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_READ) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let page = UserResponse::list(&state, &query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[proof_route("POST /users")]
+async fn create_user(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidRequest)] body: Json<CreateUser>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_WRITE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    body.validate().map_err(|_| AuthErrors::InvalidRequest)?;
+    validate_password(&body.password, &[&body.email, &body.username])
+        .map_err(AuthErrors::WeakPassword)?;
+
+    let user_id = match PartialUser::create_account(&state, &body, "user").await {
+        Ok(user_id) => user_id,
+        Err(AuthErrors::RoleNotFound) => return Err(AuthErrors::DefaultRoleNotFound),
+        Err(e) => return Err(e),
+    };
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::USER_CREATED, &device)
+            .by(&actor)
+            .subject(user_id)
+            .detail("email", body.email.as_str()),
+    );
+
+    let user = FullUser::find_by_id(&state, user_id).await?;
+    Ok(HttpResponse::Created().json(user))
+}
+
+#[proof_route("GET /users/{id}")]
+async fn get_user(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_READ) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let user = FullUser::find_by_id(&state, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("PATCH /users/{id}")]
+async fn update_user(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+    #[error_override(InvalidRequest)] body: Json<UpdateUserRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_WRITE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    body.validate().map_err(|_| AuthErrors::InvalidRequest)?;
+    let user = FullUser::update(&state, id.into_inner(), &body).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::USER_UPDATED, &device)
+            .by(&actor)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("POST /users/{id}/deactivate")]
+async fn deactivate_user(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_WRITE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let user = FullUser::set_active(&state, id.into_inner(), false).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::USER_DEACTIVATED, &device)
+            .by(&actor)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("POST /users/{id}/activate")]
+async fn activate_user(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_WRITE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let user = FullUser::set_active(&state, id.into_inner(), true).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::USER_ACTIVATED, &device)
+            .by(&actor)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("DELETE /users/{id}")]
+async fn delete_user(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(USERS_DELETE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let user_id = id.into_inner();
+    FullUser::delete(&state, user_id).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::USER_DELETED, &device)
+            .by(&actor)
+            .subject(user_id),
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[proof_route("POST /users/{id}/roles")]
+async fn add_user_role(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+    #[error_override(InvalidRequest)] body: Json<AssignRoleRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(ROLES_ASSIGN) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let user_id = id.into_inner();
+    PartialUser::assign_role(&state, user_id, &body.role).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::ROLE_ASSIGNED, &device)
+            .by(&actor)
+            .subject(user_id)
+            .detail("role", body.role.as_str()),
+    );
+
+    let user = UserResponse::find_by_id(&state, user_id).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("DELETE /users/{id}/roles/{role}")]
+async fn remove_user_role(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    path: Path<(Uuid, String)>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(ROLES_ASSIGN) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let (user_id, role) = path.into_inner();
+    PartialUser::remove_role(&state, user_id, &role).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::ROLE_REMOVED, &device)
+            .by(&actor)
+            .subject(user_id)
+            .detail("role", role),
+    );
+
+    let user = UserResponse::find_by_id(&state, user_id).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("PUT /roles/{name}/mfa")]
+async fn set_role_mfa(
+    auth: AuthDetails,
+    actor: AuditActor,
+    device: DeviceInfo,
+    state: Data<AppState>,
+    name: Path<String>,
+    #[error_override(InvalidRequest)] body: Json<RoleMfaRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    if !auth.has_authority(ROLES_WRITE) {
+        return Err(AuthErrors::Forbidden);
+    }
+
+    let role = Role::set_mfa_required(&state, &name, body.required).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::ROLE_MFA_CHANGED, &device)
+            .by(&actor)
+            .detail("role", role.name.as_str())
+            .detail("mfa_required", role.mfa_required),
+    );
+    Ok(HttpResponse::Ok().json(role))
+}
+
+#[proof_route("POST /me/mfa/setup")]
+async fn setup_mfa(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+) -> Result<HttpResponse, AuthErrors> {
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    let setup = MfaService::begin_enrollment(&state, user.id, &user.email).await?;
+    Ok(HttpResponse::Ok().json(setup))
+}
+
+#[proof_route("POST /me/mfa/confirm")]
+async fn confirm_mfa(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<MfaCodeRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    body.validate().map_err(|_| AuthErrors::InvalidRequest)?;
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    let recovery_codes =
+        MfaService::confirm_enrollment(&state, user.id, &user.email, &body.code).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::MFA_ENABLED, &device)
+            .actor(user.id)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[proof_route("POST /me/mfa/recovery-codes")]
+async fn regenerate_recovery_codes(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<MfaCodeRequest>,
+) -> Result<HttpResponse, LockoutErrors> {
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    LoginThrottle::check_account(&state, user.id).await?;
+
+    if !MfaService::verify(&state, user.id, &user.email, &body.code).await? {
+        LoginThrottle::record_failure(&state, Some((user.id, &user.email)), &device.ip).await?;
+        AuditLog::record(
+            &state,
+            AuditEvent::failure(actions::MFA_RECOVERY_CODES_REGENERATED, &device)
+                .actor(user.id)
+                .subject(user.id)
+                .reason(AuthErrors::InvalidMfaCode),
+        );
+        return Err(AuthErrors::InvalidMfaCode.into());
+    }
+
+    let recovery_codes = MfaService::regenerate_recovery_codes(&state, user.id).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::MFA_RECOVERY_CODES_REGENERATED, &device)
+            .actor(user.id)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[proof_route("POST /me/mfa/disable")]
+async fn disable_mfa(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<DisableMfaRequest>,
+) -> Result<HttpResponse, LockoutErrors> {
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    if MfaService::required_for(&state, user.id).await? {
+        return Err(AuthErrors::MfaRequired.into());
+    }
+    LoginThrottle::check_account(&state, user.id).await?;
+
+    if !verify_password(body.password.clone(), user.password_hash).await
+        || !MfaService::verify(&state, user.id, &user.email, &body.code).await?
+    {
+        LoginThrottle::record_failure(&state, Some((user.id, &user.email)), &device.ip).await?;
+        AuditLog::record(
+            &state,
+            AuditEvent::failure(actions::MFA_DISABLED, &device)
+                .actor(user.id)
+                .subject(user.id)
+                .reason(AuthErrors::InvalidCredentials),
+        );
+        return Err(AuthErrors::InvalidCredentials.into());
+    }
+    LoginThrottle::record_success(&state, user.id).await?;
+
+    MfaService::disable(&state, user.id).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::MFA_DISABLED, &device)
+            .actor(user.id)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[proof_route("GET /me/sessions")]
+async fn list_sessions(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+) -> Result<HttpResponse, AuthErrors> {
+    let sessions = Session::list(&state, claims.user_id, claims.sid).await?;
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[proof_route("DELETE /me/sessions/{id}")]
+async fn revoke_session(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(SessionNotFound)] id: Path<Uuid>,
+) -> Result<HttpResponse, AuthErrors> {
+    let session_id = id.into_inner();
+    Session::revoke(&state, claims.user_id, session_id).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::SESSION_REVOKED, &device)
+            .actor(claims.user_id)
+            .subject(claims.user_id)
+            .detail("session_id", session_id.to_string()),
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[proof_route("GET /me/profile")]
+async fn get_profile(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+) -> Result<HttpResponse, AuthErrors> {
+    let user = FullUser::find_by_id(&state, claims.user_id).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("PATCH /me/profile")]
+async fn update_profile(
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<UpdateUserRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    body.validate().map_err(|_| AuthErrors::InvalidRequest)?;
+    let user = FullUser::update(&state, claims.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[proof_route("POST /me/password")]
+async fn change_password(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] claims: Claims,
+    #[error_override(InvalidRequest)] body: Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, LockoutErrors> {
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    LoginThrottle::check_account(&state, user.id).await?;
+
+    if !verify_password(body.current_password.clone(), user.password_hash).await {
+        LoginThrottle::record_failure(&state, Some((user.id, &user.email)), &device.ip).await?;
+        AuditLog::record(
+            &state,
+            AuditEvent::failure(actions::PASSWORD_CHANGED, &device)
+                .actor(user.id)
+                .subject(user.id)
+                .reason(AuthErrors::IncorrectCurrentPassword),
+        );
+        return Err(AuthErrors::IncorrectCurrentPassword.into());
+    }
+    LoginThrottle::record_success(&state, user.id).await?;
+
+    validate_password(&body.new_password, &[&user.email, &user.username])
+        .map_err(AuthErrors::WeakPassword)?;
+
+    PartialUser::change_password(&state, user.id, &body.new_password).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::PASSWORD_CHANGED, &device)
+            .actor(user.id)
+            .subject(user.id),
+    );
+    let email = user.email.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = MailerService::send_password_changed_email(&email).await {
+            eprintln!("Failed to send password changed email: {e}");
+        }
+    });
+
+    let roles = PartialUser::role_names(&state, user.id).await?;
+    let tokens = PartialUser::issue_tokens(&state, user.id, roles, &device).await?;
+    Ok(HttpResponse::Ok().json(tokens))
 }
 
 #[proof_route("POST /register")]
 async fn register_user(
+    device: DeviceInfo,
     state: Data<AppState>,
-    body: Json<AuthUser>,
+    #[error_override(InvalidRequest)] body: Json<RegisterUser>,
 ) -> Result<HttpResponse, AuthErrors> {
-    if !is_valid_password(&body.password) {
-        return Err(AuthErrors::WeakPassword);
+    body.validate().map_err(|_| AuthErrors::InvalidRequest)?;
+    let identifiers: Vec<&str> = [Some(body.email.as_str()), body.username.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    validate_password(&body.password, &identifiers).map_err(AuthErrors::WeakPassword)?;
+
+    let user_id = PartialUser::create_user(&state, &body).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::REGISTER, &device)
+            .actor(user_id)
+            .subject(user_id),
+    );
+    if let Err(e) = MailerService::send_verification_email(&state, user_id, &body.email).await {
+        // The account exists already, the link can be requested again at /resend-verification
+        eprintln!("Failed to create verification token: {e}");
     }
 
-    let token = PartialUser::create_user(&state, &body).await?;
-    Ok(HttpResponse::Ok().json(token))
+    if EmailVerificationPolicy::configured() == EmailVerificationPolicy::Required {
+        return Ok(HttpResponse::Created().json(json!({
+            "message": "Account created, check your email to verify your address"
+        })));
+    }
+
+    let tokens =
+        PartialUser::issue_tokens(&state, user_id, vec!["user".to_string()], &device).await?;
+    Ok(HttpResponse::Ok().json(tokens))
 }
 
 #[proof_route("POST /login")]
 async fn login_user(
+    device: DeviceInfo,
     state: Data<AppState>,
-    body: Json<AuthUser>,
+    #[error_override(InvalidRequest)] body: Json<AuthUser>,
+) -> Result<HttpResponse, LockoutErrors> {
+    let failure = || {
+        AuditEvent::failure(actions::LOGIN, &device)
+            .detail("method", "password")
+            .detail("email", body.email.as_str())
+    };
+
+    if let Err(e) = LoginThrottle::check_ip(&state, &device.ip).await {
+        AuditLog::record(&state, failure().reason(&e));
+        return Err(e);
+    }
+
+    let user = match PartialUser::authenticate_user(&state, &body.email).await {
+        Err(AuthErrors::InvalidCredentials) => {
+            LoginThrottle::record_failure(&state, None, &device.ip).await?;
+            AuditLog::record(&state, failure().reason(AuthErrors::InvalidCredentials));
+            return Err(AuthErrors::InvalidCredentials.into());
+        }
+        result => result?,
+    };
+    if let Err(e) = LoginThrottle::check_account(&state, user.id).await {
+        AuditLog::record(&state, failure().subject(user.id).reason(&e));
+        return Err(e);
+    }
+
+    if !verify_password(body.password.clone(), user.password_hash.clone()).await {
+        LoginThrottle::record_failure(&state, Some((user.id, &user.email)), &device.ip).await?;
+        AuditLog::record(
+            &state,
+            failure()
+                .subject(user.id)
+                .reason(AuthErrors::InvalidCredentials),
+        );
+        return Err(AuthErrors::InvalidCredentials.into());
+    }
+    LoginThrottle::record_success(&state, user.id).await?;
+
+    if PasswordHasher::configured().needs_rehash(&user.password_hash)
+        && let Err(e) = PartialUser::rehash_password(&state, user.id, &body.password).await
+    {
+        // The old hash still verifies, the next login tries again
+        eprintln!("Failed to rehash password: {e}");
+    }
+
+    if !user.is_active {
+        AuditLog::record(
+            &state,
+            failure()
+                .subject(user.id)
+                .reason(AuthErrors::AccountDeactivated),
+        );
+        return Err(AuthErrors::AccountDeactivated.into());
+    }
+
+    if !user.email_verified
+        && EmailVerificationPolicy::configured() == EmailVerificationPolicy::Required
+    {
+        AuditLog::record(
+            &state,
+            failure()
+                .subject(user.id)
+                .reason(AuthErrors::EmailNotVerified),
+        );
+        return Err(AuthErrors::EmailNotVerified.into());
+    }
+
+    let success = AuditEvent::success(actions::LOGIN, &device)
+        .actor(user.id)
+        .subject(user.id)
+        .detail("method", "password");
+    if user.mfa_enabled {
+        AuditLog::record(&state, success.detail("mfa_pending", true));
+        return Ok(HttpResponse::Ok().json(MfaService::challenge(user.id)));
+    }
+
+    let tokens = PartialUser::issue_tokens(&state, user.id, user.roles, &device).await?;
+    AuditLog::record(&state, success);
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[proof_route("POST /login/mfa")]
+async fn login_mfa(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidRequest)] body: Json<MfaLoginRequest>,
+) -> Result<HttpResponse, LockoutErrors> {
+    let claims = validate_token(body.mfa_token.clone())
+        .ok()
+        .filter(|claims| claims.token_type == "mfa_pending")
+        .ok_or(AuthErrors::InvalidToken)?;
+    state
+        .revocations
+        .sync_if_stale(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+    if state.revocations.is_revoked(&claims) {
+        return Err(AuthErrors::InvalidToken.into());
+    }
+
+    let failure = || AuditEvent::failure(actions::LOGIN_MFA, &device).subject(claims.user_id);
+    if let Err(e) = LoginThrottle::check_ip(&state, &device.ip).await {
+        AuditLog::record(&state, failure().reason(&e));
+        return Err(e);
+    }
+    if let Err(e) = LoginThrottle::check_account(&state, claims.user_id).await {
+        AuditLog::record(&state, failure().reason(&e));
+        return Err(e);
+    }
+
+    let user = PartialUser::find_by_id(&state, claims.user_id).await?;
+    if !MfaService::verify(&state, user.id, &user.email, &body.code).await? {
+        LoginThrottle::record_failure(&state, Some((user.id, &user.email)), &device.ip).await?;
+        AuditLog::record(&state, failure().reason(AuthErrors::InvalidMfaCode));
+        return Err(AuthErrors::InvalidMfaCode.into());
+    }
+    LoginThrottle::record_success(&state, user.id).await?;
+
+    let roles = PartialUser::role_names(&state, user.id).await?;
+    let tokens = PartialUser::issue_tokens(&state, user.id, roles, &device).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::LOGIN_MFA, &device)
+            .actor(user.id)
+            .subject(user.id),
+    );
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[proof_route("POST /login/magic-link")]
+async fn request_magic_link(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    body: Json<MagicLinkRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let message = MailerService::send_magic_link_email(&state, &body.email, &device)
+        .await
+        .map_err(actix_web::Error::from)?;
+    Ok(HttpResponse::Ok().json(json!({ "message": message })))
+}
+
+#[proof_route("POST /login/magic-link/verify")]
+async fn verify_magic_link(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    body: Json<VerifyMagicLinkRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let failure = || AuditEvent::failure(actions::LOGIN, &device).detail("method", "magic_link");
+
+    let email = match MailerService::consume_magic_link(&state, &body.token).await {
+        Ok(email) => email,
+        Err(e) => {
+            AuditLog::record(&state, failure().reason(&e));
+            return Err(e.into());
+        }
+    };
+    let user = PartialUser::authenticate_user(&state, &email)
+        .await
+        .map_err(Into::<actix_web::Error>::into)?;
+
+    if !user.is_active {
+        AuditLog::record(
+            &state,
+            failure()
+                .subject(user.id)
+                .reason(AuthErrors::AccountDeactivated),
+        );
+        return Err(AuthErrors::AccountDeactivated.into());
+    }
+
+    let success = AuditEvent::success(actions::LOGIN, &device)
+        .actor(user.id)
+        .subject(user.id)
+        .detail("method", "magic_link");
+    if user.mfa_enabled {
+        AuditLog::record(&state, success.detail("mfa_pending", true));
+        return Ok(HttpResponse::Ok().json(MfaService::challenge(user.id)));
+    }
+
+    let tokens = PartialUser::issue_tokens(&state, user.id, user.roles, &device)
+        .await
+        .map_err(Into::<actix_web::Error>::into)?;
+    AuditLog::record(&state, success);
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[proof_route("POST /token/refresh")]
+async fn refresh_token(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    body: Json<RefreshTokenRequest>,
+) -> Result<HttpResponse, AuthErrors> {
+    // Only failures are recorded, successful refreshes would drown the log
+    let tokens = RefreshToken::rotate(&state, &body.refresh_token)
+        .await
+        .inspect_err(|e| {
+            AuditLog::record(
+                &state,
+                AuditEvent::failure(actions::TOKEN_REFRESH, &device).reason(e),
+            )
+        })?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[proof_route("POST /logout")]
+async fn logout(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] credentials: BearerAuth,
+    #[error_override(InvalidRequest)] body: Option<Json<LogoutRequest>>,
 ) -> Result<HttpResponse, AuthErrors> {
-    let user = PartialUser::authenticate_user(&state, &body.email).await?;
+    let claims = validate_token(credentials.token().to_owned())
+        .ok()
+        .filter(|claims| claims.token_type == "access")
+        .ok_or(AuthErrors::InvalidToken)?;
 
-    if !verify_password(body.password.clone(), user.password_hash) {
-        return Err(AuthErrors::InvalidCredentials);
+    PartialUser::revoke_access_token(&state, &claims).await?;
+    if let Some(token) = body.and_then(|body| body.into_inner().refresh_token) {
+        RefreshToken::revoke_family(&state, claims.user_id, &token).await?;
     }
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::LOGOUT, &device)
+            .actor(claims.user_id)
+            .subject(claims.user_id),
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    let token = PartialUser::generate_user_token(user.id, user.role_name)?;
-    Ok(HttpResponse::Ok().json(token))
+#[proof_route("POST /logout/all")]
+async fn logout_all(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    #[error_override(InvalidToken)] credentials: BearerAuth,
+) -> Result<HttpResponse, AuthErrors> {
+    let claims = validate_token(credentials.token().to_owned())
+        .ok()
+        .filter(|claims| claims.token_type == "access")
+        .ok_or(AuthErrors::InvalidToken)?;
+
+    PartialUser::revoke_tokens(&state, claims.user_id).await?;
+    AuditLog::record(
+        &state,
+        AuditEvent::success(actions::LOGOUT_ALL, &device)
+            .actor(claims.user_id)
+            .subject(claims.user_id),
+    );
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[proof_route("POST /forgot-password")]
 async fn forgot_password(
+    device: DeviceInfo,
     state: Data<AppState>,
     body: Json<ForgotPasswordRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let message = MailerService::send_password_reset_email(&state, &body.email)
+    let message = MailerService::send_password_reset_email(&state, &body.email, &device)
         .await
         .map_err(actix_web::Error::from)?;
     Ok(HttpResponse::Ok().json(ForgotPasswordResponse { message }))
@@ -104,15 +1161,42 @@ async fn forgot_password(
 
 #[proof_route("POST /reset-password")]
 async fn reset_password(
+    device: DeviceInfo,
     state: Data<AppState>,
     body: Json<ResetPasswordRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if !is_valid_password(&body.new_password) {
-        return Err(actix_web::Error::from(MailerErrors::PasswordHashError));
-    }
+    let message = MailerService::reset_password(&state, &body.token, &body.new_password, &device)
+        .await
+        .map_err(actix_web::Error::from)?;
+    Ok(HttpResponse::Ok().json(json!({ "message": message })))
+}
+
+#[proof_route("POST /verify-email")]
+async fn verify_email(
+    device: DeviceInfo,
+    state: Data<AppState>,
+    body: Json<VerifyEmailRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let message = MailerService::verify_email(&state, &body.token, &device)
+        .await
+        .map_err(actix_web::Error::from)?;
+    Ok(HttpResponse::Ok().json(json!({ "message": message })))
+}
 
-    let message = MailerService::reset_password(&state, &body.token, &body.new_password)
+#[proof_route("POST /resend-verification")]
+async fn resend_verification(
+    state: Data<AppState>,
+    body: Json<ResendVerificationRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let message = MailerService::resend_verification_email(&state, &body.email)
         .await
         .map_err(actix_web::Error::from)?;
     Ok(HttpResponse::Ok().json(json!({ "message": message })))
 }
+
+#[proof_route("GET /.well-known/jwks.json")]
+async fn jwks() -> Result<HttpResponse, AuthErrors> {
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "public, max-age=300"))
+        .json(SigningKeys::configured().jwks()))
+}
@@ -4,30 +4,95 @@ use uuid::Uuid;
 
 use crate::{
     AppState,
-    helpers::hash_password::hash_password,
-    middlewares::jwt::{TokenStruct, generate_token},
+    config::Config,
+    helpers::{
+        hash_password::hash_password,
+        token::{generate_opaque_token, hash_token},
+    },
+    mailer::MailerService,
+    middlewares::jwt::{AuthTokens, Claims, generate_token},
     users::{
-        AuthUser,
-        entities::{PartialUser, UserWithRole},
+        AuthUser, CreateUser, ListUsersQuery, RegisterUser, UpdateUserRequest, UserPage,
+        UserResponse,
+        entities::{DeviceInfo, FullUser, PartialUser, RefreshToken, Role, Session, UserWithRoles},
         errors::auth::AuthErrors,
     },
 };
 
+/// Generated usernames tried before giving up on a registration.
+const USERNAME_ATTEMPTS: usize = 5;
+
 impl PartialUser {
+    /// Registers a user holding the default `user` role.
     pub async fn create_user(
+        state: &AppState,
+        registration: &RegisterUser,
+    ) -> Result<Uuid, AuthErrors> {
+        let result = Self::insert_account(
+            state,
+            registration.username.as_deref(),
+            &registration.email,
+            None,
+            &registration.password,
+            "user",
+        )
+        .await;
+
+        match result {
+            Err(AuthErrors::RoleNotFound) => Err(AuthErrors::DefaultRoleNotFound),
+            result => result,
+        }
+    }
+
+    /// Creates a user holding `role_name`, with a username generated from the email.
+    pub async fn create_user_with_role(
         state: &AppState,
         auth_user: &AuthUser,
-    ) -> Result<TokenStruct, AuthErrors> {
-        let hashed_password =
-            hash_password(auth_user.password.clone()).map_err(|_| AuthErrors::PasswordHashError)?;
+        role_name: &str,
+    ) -> Result<Uuid, AuthErrors> {
+        Self::insert_account(
+            state,
+            None,
+            &auth_user.email,
+            None,
+            &auth_user.password,
+            role_name,
+        )
+        .await
+    }
 
-        let email = auth_user.email.clone();
-        let username = auth_user
-            .email
-            .split('@')
-            .next()
-            .unwrap_or("user")
-            .to_string();
+    /// Creates a user with an explicit username and full name, holding `role_name`.
+    pub async fn create_account(
+        state: &AppState,
+        new_user: &CreateUser,
+        role_name: &str,
+    ) -> Result<Uuid, AuthErrors> {
+        Self::insert_account(
+            state,
+            Some(&new_user.username),
+            &new_user.email,
+            new_user.full_name.as_deref(),
+            &new_user.password,
+            role_name,
+        )
+        .await
+    }
+
+    /// Inserts a user and its role.
+    ///
+    /// Without an explicit username one is derived from the email, adding a random suffix
+    /// when it is already taken.
+    async fn insert_account(
+        state: &AppState,
+        username: Option<&str>,
+        email: &str,
+        full_name: Option<&str>,
+        password: &str,
+        role_name: &str,
+    ) -> Result<Uuid, AuthErrors> {
+        let hashed_password = hash_password(password.to_string())
+            .await
+            .map_err(|_| AuthErrors::PasswordHashError)?;
 
         let mut tx = state
             .db_pool
@@ -35,29 +100,48 @@ impl PartialUser {
             .await
             .map_err(|_| AuthErrors::DatabaseError)?;
 
-        let user_result = sqlx::query!(
-            "INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3) RETURNING id",
-            username,
-            email,
-            hashed_password
-        )
-        .fetch_one(&mut *tx)
-        .await;
+        let mut user_id = None;
+        for attempt in 0..USERNAME_ATTEMPTS {
+            let candidate = match username {
+                Some(username) => username.to_string(),
+                None => Self::fallback_username(email, attempt),
+            };
 
-        let user_id = match user_result {
-            Ok(user) => user.id,
-            Err(e) => {
-                let _ = tx.rollback().await;
-                if let sqlx::Error::Database(db_err) = &e
-                    && db_err.code() == Some(Cow::Borrowed("23505"))
-                {
-                    return Err(AuthErrors::EmailAlreadyRegistered);
+            // Username conflicts are skipped so another candidate can be tried in the same
+            // transaction, email conflicts still fail the insert
+            let user_result = sqlx::query!(
+                "INSERT INTO users (username, email, full_name, password_hash)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (username) DO NOTHING
+                 RETURNING id",
+                candidate,
+                email,
+                full_name,
+                hashed_password
+            )
+            .fetch_optional(&mut *tx)
+            .await;
+
+            match user_result {
+                Ok(Some(user)) => {
+                    user_id = Some(user.id);
+                    break;
+                }
+                Ok(None) if username.is_none() => continue,
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    return Err(unique_violation(&e).unwrap_or(AuthErrors::DatabaseError));
                 }
-                return Err(AuthErrors::DatabaseError);
             }
+        }
+
+        let Some(user_id) = user_id else {
+            let _ = tx.rollback().await;
+            return Err(AuthErrors::UsernameTaken);
         };
 
-        let role_result = sqlx::query!("SELECT id FROM catalogs.roles WHERE name = 'user'")
+        let role_result = sqlx::query!("SELECT id FROM catalogs.roles WHERE name = $1", role_name)
             .fetch_one(&mut *tx)
             .await;
 
@@ -65,7 +149,7 @@ impl PartialUser {
             Ok(role) => role.id,
             Err(_) => {
                 let _ = tx.rollback().await;
-                return Err(AuthErrors::DefaultRoleNotFound);
+                return Err(AuthErrors::RoleNotFound);
             }
         };
 
@@ -88,65 +172,695 @@ impl PartialUser {
             return Err(AuthErrors::TransactionError);
         }
 
-        Self::generate_user_token(user_id, "user".to_string())
+        Ok(user_id)
     }
 
-    pub async fn authenticate_user(
-        state: &AppState,
-        email: &str,
-    ) -> Result<UserWithRole, AuthErrors> {
-        let user_result = sqlx::query_as::<_, UserWithRole>(
-            "SELECT u.id, u.email, u.password_hash, r.name as role_name
-             FROM users u 
-             JOIN users_role ur ON u.id = ur.user_id 
-             JOIN catalogs.roles r ON ur.role_id = r.id 
-             WHERE u.email = $1 
-             LIMIT 1",
+    /// Username derived from the local part of the email, e.g. `alice` then `alice_3f9a1c`.
+    fn fallback_username(email: &str, attempt: usize) -> String {
+        let mut base: String = email
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            .take(40)
+            .collect();
+        if base.len() < 3 {
+            base = "user".to_string();
+        }
+
+        if attempt == 0 {
+            return base;
+        }
+        let suffix = Uuid::new_v4().simple().to_string();
+        format!("{base}_{}", &suffix[..6])
+    }
+
+    pub async fn find_by_email(state: &AppState, email: &str) -> Result<PartialUser, AuthErrors> {
+        sqlx::query_as::<_, PartialUser>(
+            "SELECT id, email, username, password_hash FROM users WHERE email = $1",
         )
         .bind(email)
         .fetch_optional(&state.db_pool)
         .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)
+    }
+
+    pub async fn find_by_id(state: &AppState, user_id: Uuid) -> Result<PartialUser, AuthErrors> {
+        sqlx::query_as::<_, PartialUser>(
+            "SELECT id, email, username, password_hash FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)
+    }
+
+    /// Replaces the password of a user and revokes every token issued before the change.
+    pub async fn change_password(
+        state: &AppState,
+        user_id: Uuid,
+        new_password: &str,
+    ) -> Result<(), AuthErrors> {
+        let hashed_password = hash_password(new_password.to_string())
+            .await
+            .map_err(|_| AuthErrors::PasswordHashError)?;
+
+        sqlx::query!(
+            "UPDATE users SET password_hash = $1 WHERE id = $2",
+            hashed_password,
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
         .map_err(|_| AuthErrors::DatabaseError)?;
 
-        match user_result {
-            Some(user) => Ok(user),
-            None => {
-                // Try to get user without role (fallback)
-                let user = sqlx::query_as::<_, PartialUser>(
-                    "SELECT id, email, password_hash FROM users WHERE email = $1",
-                )
-                .bind(email)
-                .fetch_optional(&state.db_pool)
-                .await
-                .map_err(|_| AuthErrors::DatabaseError)?;
+        Self::revoke_tokens(state, user_id).await
+    }
 
-                match user {
-                    Some(user) => Ok(UserWithRole {
-                        id: user.id,
-                        email: user.email,
-                        password_hash: user.password_hash,
-                        role_name: "user".to_string(), // default role
-                    }),
-                    None => Err(AuthErrors::InvalidCredentials),
-                }
+    /// Stores the password with the configured hasher, keeping the user's sessions.
+    ///
+    /// Called after a successful login when the stored hash uses an outdated algorithm or
+    /// outdated parameters.
+    pub async fn rehash_password(
+        state: &AppState,
+        user_id: Uuid,
+        password: &str,
+    ) -> Result<(), AuthErrors> {
+        let hashed_password = hash_password(password.to_string())
+            .await
+            .map_err(|_| AuthErrors::PasswordHashError)?;
+
+        sqlx::query!(
+            "UPDATE users SET password_hash = $1 WHERE id = $2",
+            hashed_password,
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Grants a role to a user. Assigning a role the user already holds is a no-op.
+    pub async fn assign_role(
+        state: &AppState,
+        user_id: Uuid,
+        role_name: &str,
+    ) -> Result<(), AuthErrors> {
+        let role = sqlx::query!("SELECT id FROM catalogs.roles WHERE name = $1", role_name)
+            .fetch_optional(&state.db_pool)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?
+            .ok_or(AuthErrors::RoleNotFound)?;
+
+        sqlx::query!(
+            "INSERT INTO users_role (user_id, role_id) VALUES ($1, $2)
+             ON CONFLICT (user_id, role_id) DO NOTHING",
+            user_id,
+            role.id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                AuthErrors::UserNotFound
             }
-        }
+            _ => AuthErrors::DatabaseError,
+        })?;
+
+        Ok(())
     }
 
-    pub fn generate_user_token(
+    /// Takes a role away from a user.
+    pub async fn remove_role(
+        state: &AppState,
         user_id: Uuid,
-        user_role: String,
-    ) -> Result<TokenStruct, AuthErrors> {
+        role_name: &str,
+    ) -> Result<(), AuthErrors> {
+        let role = sqlx::query!("SELECT id FROM catalogs.roles WHERE name = $1", role_name)
+            .fetch_optional(&state.db_pool)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?
+            .ok_or(AuthErrors::RoleNotFound)?;
+
+        let removed = sqlx::query!(
+            "DELETE FROM users_role WHERE user_id = $1 AND role_id = $2",
+            user_id,
+            role.id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        if removed.rows_affected() == 0 {
+            return Err(AuthErrors::RoleNotAssigned);
+        }
+
+        Ok(())
+    }
+
+    pub async fn mark_email_verified(state: &AppState, user_id: Uuid) -> Result<(), AuthErrors> {
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Invalidates every access and refresh token issued to the user so far.
+    ///
+    /// The cutoff is taken from the clock that dates access tokens rather than the
+    /// database's, so tokens issued right after, e.g. by a password change, stay valid.
+    pub async fn revoke_tokens(state: &AppState, user_id: Uuid) -> Result<(), AuthErrors> {
+        let revoked_at = chrono::Utc::now();
+        let updated = sqlx::query!(
+            "UPDATE users SET tokens_revoked_at = $2 WHERE id = $1",
+            user_id,
+            revoked_at
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        if updated.rows_affected() == 0 {
+            return Err(AuthErrors::UserNotFound);
+        }
+        state
+            .revocations
+            .revoke_user(user_id, revoked_at.timestamp_millis());
+
+        sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Revokes a single access token, e.g. on logout.
+    pub async fn revoke_access_token(state: &AppState, claims: &Claims) -> Result<(), AuthErrors> {
+        let expires_at = chrono::DateTime::from_timestamp(claims.exp as i64, 0)
+            .ok_or(AuthErrors::InvalidToken)?;
+
+        sqlx::query!(
+            "INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)
+             ON CONFLICT (jti) DO NOTHING",
+            claims.jti,
+            claims.user_id,
+            expires_at
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        state
+            .revocations
+            .revoke_token(claims.jti, claims.exp as i64);
+
+        Ok(())
+    }
+
+    pub async fn role_names(state: &AppState, user_id: Uuid) -> Result<Vec<String>, AuthErrors> {
+        sqlx::query_scalar!(
+            "SELECT r.name FROM users_role ur
+             JOIN catalogs.roles r ON ur.role_id = r.id
+             WHERE ur.user_id = $1
+             ORDER BY r.name",
+            user_id
+        )
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)
+    }
+
+    pub async fn authenticate_user(
+        state: &AppState,
+        email: &str,
+    ) -> Result<UserWithRoles, AuthErrors> {
+        sqlx::query_as::<_, UserWithRoles>(
+            "SELECT u.id, u.email, u.password_hash, u.is_active,
+                    u.email_verified_at IS NOT NULL AS email_verified,
+                    u.mfa_enabled_at IS NOT NULL AS mfa_enabled,
+                    COALESCE(
+                        array_agg(r.name ORDER BY r.name) FILTER (WHERE r.name IS NOT NULL),
+                        '{}'
+                    ) AS roles
+             FROM users u
+             LEFT JOIN users_role ur ON u.id = ur.user_id
+             LEFT JOIN catalogs.roles r ON ur.role_id = r.id
+             WHERE u.email = $1
+             GROUP BY u.id",
+        )
+        .bind(email)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::InvalidCredentials)
+    }
+
+    pub fn generate_access_token(user_id: Uuid, roles: Vec<String>, session_id: Uuid) -> String {
         let iss = "PGMQ-Backend";
-        let duration_in_minutes: i64 = 525600; // 1 year
-        let token = generate_token(
+        generate_token(
             iss.to_string(),
-            duration_in_minutes,
+            Config::from_env().access_token_ttl_minutes,
             "access".to_owned(),
             user_id,
-            user_role,
+            roles,
+            Some(session_id),
+        )
+    }
+
+    /// Starts a session on `device` and issues its access token and the first refresh
+    /// token of its token family.
+    ///
+    /// The user is notified by email when the device is new to their account.
+    pub async fn issue_tokens(
+        state: &AppState,
+        user_id: Uuid,
+        roles: Vec<String>,
+        device: &DeviceInfo,
+    ) -> Result<AuthTokens, AuthErrors> {
+        let session_id = Uuid::new_v4();
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+        let new_device = Session::start(&mut tx, session_id, user_id, device).await?;
+        let refresh_token = RefreshToken::create(&mut tx, user_id, session_id).await?;
+        tx.commit()
+            .await
+            .map_err(|_| AuthErrors::TransactionError)?;
+
+        if new_device {
+            let user = Self::find_by_id(state, user_id).await?;
+            let device = device.clone();
+            actix_web::rt::spawn(async move {
+                if let Err(e) = MailerService::send_new_device_email(&user.email, &device).await {
+                    eprintln!("Failed to send new device email: {e}");
+                }
+            });
+        }
+
+        Ok(Self::auth_tokens(user_id, roles, session_id, refresh_token))
+    }
+
+    fn auth_tokens(
+        user_id: Uuid,
+        roles: Vec<String>,
+        session_id: Uuid,
+        refresh_token: String,
+    ) -> AuthTokens {
+        AuthTokens {
+            access_token: Self::generate_access_token(user_id, roles, session_id),
+            refresh_token,
+            token_type: "Bearer".to_string(),
+            expires_in: Config::from_env().access_token_ttl_minutes * 60,
+        }
+    }
+}
+
+impl RefreshToken {
+    async fn create(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        user_id: Uuid,
+        family_id: Uuid,
+    ) -> Result<String, AuthErrors> {
+        let token = generate_opaque_token();
+        let expires_at =
+            chrono::Utc::now() + chrono::Duration::days(Config::from_env().refresh_token_ttl_days);
+
+        sqlx::query!(
+            "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+             VALUES ($1, $2, $3, $4)",
+            user_id,
+            family_id,
+            hash_token(&token),
+            expires_at
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(token)
+    }
+
+    /// Revokes every refresh token of the family the given token belongs to.
+    pub async fn revoke_family(
+        state: &AppState,
+        user_id: Uuid,
+        token: &str,
+    ) -> Result<(), AuthErrors> {
+        sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = NOW()
+             WHERE revoked_at IS NULL
+               AND family_id = (
+                   SELECT family_id FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2
+               )",
+            hash_token(token),
+            user_id
+        )
+        .execute(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Exchanges a refresh token for a new token pair.
+    ///
+    /// Each refresh token can be used once. Presenting a token that was already rotated
+    /// means it leaked, so the whole family is revoked and the user has to log in again.
+    pub async fn rotate(state: &AppState, token: &str) -> Result<AuthTokens, AuthErrors> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        let record = sqlx::query_as::<_, RefreshToken>(
+            "SELECT id, user_id, family_id, token_hash, expires_at, rotated_at, revoked_at,
+                    created_at
+             FROM refresh_tokens
+             WHERE token_hash = $1
+             FOR UPDATE",
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::InvalidRefreshToken)?;
+
+        if record.revoked_at.is_some() {
+            return Err(AuthErrors::InvalidRefreshToken);
+        }
+
+        if record.rotated_at.is_some() {
+            sqlx::query!(
+                "UPDATE refresh_tokens SET revoked_at = NOW()
+                 WHERE family_id = $1 AND revoked_at IS NULL",
+                record.family_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+            tx.commit()
+                .await
+                .map_err(|_| AuthErrors::TransactionError)?;
+
+            return Err(AuthErrors::RefreshTokenReused);
+        }
+
+        if record.is_expired() {
+            return Err(AuthErrors::InvalidRefreshToken);
+        }
+
+        sqlx::query!(
+            "UPDATE refresh_tokens SET rotated_at = NOW() WHERE id = $1",
+            record.id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        let refresh_token = Self::create(&mut tx, record.user_id, record.family_id).await?;
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = NOW() WHERE id = $1",
+            record.family_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+        tx.commit()
+            .await
+            .map_err(|_| AuthErrors::TransactionError)?;
+
+        let roles = PartialUser::role_names(state, record.user_id).await?;
+        Ok(PartialUser::auth_tokens(
+            record.user_id,
+            roles,
+            record.family_id,
+            refresh_token,
+        ))
+    }
+}
+
+impl FullUser {
+    pub async fn find_by_id(state: &AppState, user_id: Uuid) -> Result<FullUser, AuthErrors> {
+        sqlx::query_as::<_, FullUser>(
+            "SELECT id, username, email, full_name, password_hash, is_active, email_verified_at,
+                    created_at, updated_at
+             FROM users
+             WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)
+    }
+
+    /// Updates the fields present in the request, leaving the others untouched.
+    pub async fn update(
+        state: &AppState,
+        user_id: Uuid,
+        changes: &UpdateUserRequest,
+    ) -> Result<FullUser, AuthErrors> {
+        sqlx::query_as::<_, FullUser>(
+            "UPDATE users
+             SET username = COALESCE($2, username),
+                 full_name = COALESCE($3, full_name)
+             WHERE id = $1
+             RETURNING id, username, email, full_name, password_hash, is_active,
+                       email_verified_at, created_at, updated_at",
+        )
+        .bind(user_id)
+        .bind(&changes.username)
+        .bind(&changes.full_name)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|e| unique_violation(&e).unwrap_or(AuthErrors::DatabaseError))?
+        .ok_or(AuthErrors::UserNotFound)
+    }
+
+    /// Activates or deactivates an account. Deactivating also revokes every token of the user.
+    pub async fn set_active(
+        state: &AppState,
+        user_id: Uuid,
+        is_active: bool,
+    ) -> Result<FullUser, AuthErrors> {
+        let user = sqlx::query_as::<_, FullUser>(
+            "UPDATE users
+             SET is_active = $2
+             WHERE id = $1
+             RETURNING id, username, email, full_name, password_hash, is_active,
+                       email_verified_at, created_at, updated_at",
+        )
+        .bind(user_id)
+        .bind(is_active)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)?;
+
+        if !is_active {
+            PartialUser::revoke_tokens(state, user_id).await?;
+        }
+
+        Ok(user)
+    }
+
+    pub async fn delete(state: &AppState, user_id: Uuid) -> Result<(), AuthErrors> {
+        let deleted = sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
+            .execute(&state.db_pool)
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        if deleted.rows_affected() == 0 {
+            return Err(AuthErrors::UserNotFound);
+        }
+
+        Ok(())
+    }
+}
+
+impl UserResponse {
+    /// Lists users matching the query filters, one page at a time.
+    pub async fn list(state: &AppState, query: &ListUsersQuery) -> Result<UserPage, AuthErrors> {
+        let limit = query.limit.unwrap_or(50).clamp(1, 100);
+        let offset = query.offset.unwrap_or(0).max(0);
+        let filters = "($1::TEXT IS NULL OR strpos(lower(u.email), lower($1)) > 0)
+               AND ($2::TEXT IS NULL OR EXISTS (
+                   SELECT 1 FROM users_role fur
+                   JOIN catalogs.roles fr ON fur.role_id = fr.id
+                   WHERE fur.user_id = u.id AND fr.name = $2
+               ))
+               AND ($3::BOOLEAN IS NULL OR u.is_active = $3)
+               AND ($4::TIMESTAMPTZ IS NULL OR u.created_at >= $4)
+               AND ($5::TIMESTAMPTZ IS NULL OR u.created_at < $5)";
+
+        let total =
+            sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM users u WHERE {filters}"))
+                .bind(&query.email)
+                .bind(&query.role)
+                .bind(query.active)
+                .bind(query.created_from)
+                .bind(query.created_to)
+                .fetch_one(&state.db_pool)
+                .await
+                .map_err(|_| AuthErrors::DatabaseError)?;
+
+        // Sort column and direction come from enums, never from the raw query string
+        let users = sqlx::query_as::<_, UserResponse>(&format!(
+            "SELECT u.id, u.username, u.email, u.full_name, u.is_active, u.email_verified_at,
+                    COALESCE(
+                        array_agg(r.name ORDER BY r.name) FILTER (WHERE r.name IS NOT NULL),
+                        '{{}}'
+                    ) AS roles,
+                    u.created_at, u.updated_at
+             FROM users u
+             LEFT JOIN users_role ur ON u.id = ur.user_id
+             LEFT JOIN catalogs.roles r ON ur.role_id = r.id
+             WHERE {filters}
+             GROUP BY u.id
+             ORDER BY {} {}, u.id
+             LIMIT $6 OFFSET $7",
+            query.sort.column(),
+            query.order.keyword(),
+        ))
+        .bind(&query.email)
+        .bind(&query.role)
+        .bind(query.active)
+        .bind(query.created_from)
+        .bind(query.created_to)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(UserPage {
+            users,
+            total,
+            limit,
+            offset,
+        })
+    }
+
+    pub async fn find_by_id(state: &AppState, user_id: Uuid) -> Result<UserResponse, AuthErrors> {
+        sqlx::query_as::<_, UserResponse>(
+            "SELECT u.id, u.username, u.email, u.full_name, u.is_active, u.email_verified_at,
+                    COALESCE(
+                        array_agg(r.name ORDER BY r.name) FILTER (WHERE r.name IS NOT NULL),
+                        '{}'
+                    ) AS roles,
+                    u.created_at, u.updated_at
+             FROM users u
+             LEFT JOIN users_role ur ON u.id = ur.user_id
+             LEFT JOIN catalogs.roles r ON ur.role_id = r.id
+             WHERE u.id = $1
+             GROUP BY u.id",
+        )
+        .bind(user_id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::UserNotFound)
+    }
+}
+
+impl Role {
+    pub async fn list(state: &AppState) -> Result<Vec<Role>, AuthErrors> {
+        sqlx::query_as::<_, Role>(
+            "SELECT id, name, mfa_required, created_at FROM catalogs.roles ORDER BY name",
+        )
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)
+    }
+
+    /// Requires (or stops requiring) two-factor authentication from holders of a role.
+    pub async fn set_mfa_required(
+        state: &AppState,
+        name: &str,
+        required: bool,
+    ) -> Result<Role, AuthErrors> {
+        sqlx::query_as::<_, Role>(
+            "UPDATE catalogs.roles SET mfa_required = $2
+             WHERE name = $1
+             RETURNING id, name, mfa_required, created_at",
+        )
+        .bind(name)
+        .bind(required)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::RoleNotFound)
+    }
+}
+
+/// Maps a unique violation on `users` to the conflicting field.
+fn unique_violation(e: &sqlx::Error) -> Option<AuthErrors> {
+    let sqlx::Error::Database(db_err) = e else {
+        return None;
+    };
+    if db_err.code() != Some(Cow::Borrowed("23505")) {
+        return None;
+    }
+
+    match db_err.constraint() {
+        Some("users_username_key") => Some(AuthErrors::UsernameTaken),
+        _ => Some(AuthErrors::EmailAlreadyRegistered),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_username_uses_email_local_part() {
+        assert_eq!(
+            PartialUser::fallback_username("alice@example.com", 0),
+            "alice"
+        );
+        assert_eq!(
+            PartialUser::fallback_username("a.l-i_ce+tag@example.com", 0),
+            "a.l-i_cetag"
         );
+    }
+
+    #[test]
+    fn fallback_username_replaces_short_local_parts() {
+        assert_eq!(PartialUser::fallback_username("al@example.com", 0), "user");
+        assert_eq!(PartialUser::fallback_username("+++@example.com", 0), "user");
+    }
+
+    #[test]
+    fn fallback_username_truncates_long_local_parts() {
+        let email = format!("{}@example.com", "a".repeat(60));
+        assert_eq!(PartialUser::fallback_username(&email, 0), "a".repeat(40));
+    }
+
+    #[test]
+    fn fallback_username_adds_random_suffix_on_retries() {
+        let first = PartialUser::fallback_username("alice@example.com", 1);
+        let second = PartialUser::fallback_username("alice@example.com", 2);
 
-        Ok(TokenStruct { token })
+        let suffix = first.strip_prefix("alice_").expect("suffixed username");
+        assert_eq!(suffix.len(), 6);
+        assert!(suffix.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
     }
 }
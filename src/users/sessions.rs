@@ -0,0 +1,127 @@
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    users::{
+        entities::{DeviceInfo, Session},
+        errors::auth::AuthErrors,
+    },
+};
+
+impl Session {
+    /// Records a login, using the id of its refresh token family.
+    ///
+    /// Returns whether it comes from a new device, meaning the user has logged in before
+    /// but never with this user agent. A user's very first login is not a new device.
+    pub async fn start(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        id: Uuid,
+        user_id: Uuid,
+        device: &DeviceInfo,
+    ) -> Result<bool, AuthErrors> {
+        let seen = sqlx::query!(
+            r#"SELECT COUNT(*) > 0 as "logged_in_before!",
+                      COALESCE(bool_or(user_agent IS NOT DISTINCT FROM $2), FALSE) as "known!"
+               FROM sessions WHERE user_id = $1"#,
+            user_id,
+            device.user_agent
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, user_agent, ip) VALUES ($1, $2, $3, $4)",
+            id,
+            user_id,
+            device.user_agent,
+            device.ip
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        Ok(seen.logged_in_before && !seen.known)
+    }
+
+    /// Sessions of a user that can still be refreshed, most recently used first.
+    pub async fn list(
+        state: &AppState,
+        user_id: Uuid,
+        current: Option<Uuid>,
+    ) -> Result<Vec<Session>, AuthErrors> {
+        let mut sessions = sqlx::query_as::<_, Session>(
+            "SELECT s.id, s.user_agent, s.ip, s.created_at, s.last_seen_at
+             FROM sessions s
+             WHERE s.user_id = $1
+               AND s.revoked_at IS NULL
+               AND EXISTS (
+                   SELECT 1 FROM refresh_tokens rt
+                   WHERE rt.family_id = s.id
+                     AND rt.revoked_at IS NULL
+                     AND rt.rotated_at IS NULL
+                     AND rt.expires_at > NOW()
+               )
+             ORDER BY s.last_seen_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        for session in &mut sessions {
+            session.current = Some(session.id) == current;
+        }
+        Ok(sessions)
+    }
+
+    /// Signs a session of the user out, revoking its refresh tokens and access tokens.
+    pub async fn revoke(state: &AppState, user_id: Uuid, id: Uuid) -> Result<(), AuthErrors> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| AuthErrors::DatabaseError)?;
+
+        let revoked_at = sqlx::query_scalar!(
+            r#"UPDATE sessions SET revoked_at = COALESCE(revoked_at, NOW())
+               WHERE id = $1 AND user_id = $2
+               RETURNING revoked_at as "revoked_at!""#,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?
+        .ok_or(AuthErrors::SessionNotFound)?;
+
+        sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = NOW()
+             WHERE family_id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthErrors::DatabaseError)?;
+
+        tx.commit()
+            .await
+            .map_err(|_| AuthErrors::TransactionError)?;
+
+        state.revocations.revoke_session(id, revoked_at.timestamp());
+        Ok(())
+    }
+
+    /// Records activity on a session, at most once a minute.
+    pub async fn touch(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = NOW()
+             WHERE id = $1 AND last_seen_at < NOW() - INTERVAL '1 minute'",
+            id
+        )
+        .execute(&state.db_pool)
+        .await?;
+
+        Ok(())
+    }
+}
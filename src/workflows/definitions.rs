@@ -0,0 +1,61 @@
+use std::{future::Future, pin::Pin};
+
+use crate::{
+    AppState,
+    workflows::{entities::WorkflowJob, onboarding},
+};
+
+pub type StepFuture<'a> = Pin<Box<dyn Future<Output = Result<serde_json::Value, String>> + 'a>>;
+
+/// Runs a job of a step, or of its compensation when `job.compensation` is set, and returns
+/// the output merged into the workflow context. A job may run more than once, e.g. when
+/// the worker stops before reporting it, so handlers must be idempotent.
+pub type StepHandler = for<'a> fn(&'a AppState, &'a WorkflowJob) -> StepFuture<'a>;
+
+/// A single unit of work in a workflow, run by the workflow worker consuming `queue`.
+///
+/// A step is retried through the queue's visibility timeout until it has been read
+/// `max_attempts` times, after which the workflow starts compensating.
+#[derive(Debug)]
+pub struct WorkflowStep {
+    pub name: &'static str,
+    pub queue: &'static str,
+    pub compensation_queue: Option<&'static str>,
+    pub max_attempts: i32,
+    pub handler: StepHandler,
+}
+
+#[derive(Debug)]
+pub struct WorkflowDefinition {
+    pub name: &'static str,
+    pub steps: &'static [WorkflowStep],
+}
+
+pub const ONBOARDING: WorkflowDefinition = WorkflowDefinition {
+    name: "onboarding",
+    steps: &[
+        WorkflowStep {
+            name: "create_account",
+            queue: "onboarding_create_account",
+            compensation_queue: Some("onboarding_delete_account"),
+            max_attempts: 5,
+            handler: onboarding::create_account,
+        },
+        WorkflowStep {
+            name: "provision_store",
+            queue: "onboarding_provision_store",
+            compensation_queue: Some("onboarding_deprovision_store"),
+            max_attempts: 5,
+            handler: onboarding::provision_store,
+        },
+        WorkflowStep {
+            name: "send_welcome_email",
+            queue: "onboarding_send_welcome_email",
+            compensation_queue: None,
+            max_attempts: 3,
+            handler: onboarding::send_welcome_email,
+        },
+    ],
+};
+
+pub const DEFINITIONS: &[&WorkflowDefinition] = &[&ONBOARDING];
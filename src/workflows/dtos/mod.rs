@@ -0,0 +1,5 @@
+mod list;
+pub use list::*;
+
+mod start;
+pub use start::*;
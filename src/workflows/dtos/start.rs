@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct StartWorkflowRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub workflow: String,
+    #[serde(default = "empty_context")]
+    pub context: serde_json::Value,
+}
+
+fn empty_context() -> serde_json::Value {
+    serde_json::json!({})
+}
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_COMPLETED: &str = "completed";
+pub const STATUS_COMPENSATING: &str = "compensating";
+pub const STATUS_COMPENSATED: &str = "compensated";
+pub const STATUS_FAILED: &str = "failed";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct WorkflowInstance {
+    pub id: Uuid,
+    pub workflow_name: String,
+    pub status: String,
+    pub current_step: i32,
+    pub context: serde_json::Value,
+    pub last_error: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Message enqueued for every workflow step and compensation step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowJob {
+    pub instance_id: Uuid,
+    pub workflow: String,
+    pub step: i32,
+    pub step_name: String,
+    pub compensation: bool,
+    pub context: serde_json::Value,
+}
@@ -0,0 +1,33 @@
+use actix_failwrap::ErrorResponse;
+use thiserror::Error;
+
+#[derive(Debug, ErrorResponse, Error)]
+pub enum WorkflowErrors {
+    #[error("Invalid request")]
+    #[status_code(400)]
+    InvalidRequest,
+
+    #[error("Unknown workflow")]
+    #[status_code(400)]
+    UnknownWorkflow,
+
+    #[error("Unknown workflow step")]
+    #[status_code(400)]
+    UnknownStep,
+
+    #[error("Access denied")]
+    #[status_code(403)]
+    Forbidden,
+
+    #[error("Workflow instance not found")]
+    #[status_code(404)]
+    InstanceNotFound,
+
+    #[error("Queue error")]
+    #[status_code(500)]
+    QueueError,
+
+    #[error("Database error")]
+    #[status_code(500)]
+    DatabaseError,
+}
@@ -0,0 +1,20 @@
+pub mod entities {
+    mod workflow;
+    pub use workflow::*;
+}
+
+mod definitions;
+pub use definitions::*;
+
+mod dtos;
+pub use dtos::*;
+
+pub mod errors;
+
+mod onboarding;
+
+mod routes;
+pub use routes::config as routes;
+
+mod service;
+pub use service::*;
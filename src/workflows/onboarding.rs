@@ -0,0 +1,118 @@
+//! Step handlers of the [`ONBOARDING`](super::ONBOARDING) workflow, started with
+//! `{ "email": "..." }` as context.
+
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    helpers::token::generate_opaque_token,
+    mailer::MailerService,
+    users::{
+        AuthUser,
+        entities::{FullUser, PartialUser},
+        errors::auth::AuthErrors,
+    },
+    workflows::{StepFuture, WorkflowService, entities::WorkflowJob},
+};
+
+/// Role granted by the `provision_store` step.
+const STORE_ROLE: &str = "store_admin";
+
+/// Creates an account holding the `user` role for `email`, or deletes it when compensating.
+///
+/// The password is random and never shared, the user sets one through the reset link of
+/// the welcome email. Outputs the `user_id`.
+pub fn create_account<'a>(state: &'a AppState, job: &'a WorkflowJob) -> StepFuture<'a> {
+    Box::pin(async move {
+        if job.compensation {
+            return match FullUser::delete(state, context_field(job, "user_id")?).await {
+                Ok(()) | Err(AuthErrors::UserNotFound) => Ok(serde_json::Value::Null),
+                Err(e) => Err(e.to_string()),
+            };
+        }
+
+        let auth_user = AuthUser {
+            email: context_field(job, "email")?,
+            password: generate_opaque_token(),
+        };
+        auth_user
+            .validate()
+            .map_err(|_| "The context email is not a valid email address".to_string())?;
+
+        let user_id = match PartialUser::create_user_with_role(state, &auth_user, "user").await {
+            Ok(user_id) => user_id,
+            Err(AuthErrors::EmailAlreadyRegistered) => {
+                created_by_instance(state, job, &auth_user.email).await?
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(serde_json::json!({ "user_id": user_id }))
+    })
+}
+
+/// The account a previous run of the step created for `email`, when the job is redelivered
+/// after the account was committed. An account that existed before the workflow started
+/// belongs to someone else and fails the step.
+async fn created_by_instance(
+    state: &AppState,
+    job: &WorkflowJob,
+    email: &str,
+) -> Result<Uuid, String> {
+    let user_id = PartialUser::find_by_email(state, email)
+        .await
+        .map_err(|e| e.to_string())?
+        .id;
+    let created_at = FullUser::find_by_id(state, user_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .created_at;
+    let started_at = WorkflowService::get(state, job.instance_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .created_at;
+
+    if created_at < started_at {
+        return Err(AuthErrors::EmailAlreadyRegistered.to_string());
+    }
+    Ok(user_id)
+}
+
+/// Grants the store role to the new account, or takes it back when compensating.
+pub fn provision_store<'a>(state: &'a AppState, job: &'a WorkflowJob) -> StepFuture<'a> {
+    Box::pin(async move {
+        let user_id: Uuid = context_field(job, "user_id")?;
+
+        let result = if job.compensation {
+            match PartialUser::remove_role(state, user_id, STORE_ROLE).await {
+                Err(AuthErrors::RoleNotAssigned) => Ok(()),
+                result => result,
+            }
+        } else {
+            PartialUser::assign_role(state, user_id, STORE_ROLE).await
+        };
+
+        result
+            .map(|_| serde_json::Value::Null)
+            .map_err(|e| e.to_string())
+    })
+}
+
+pub fn send_welcome_email<'a>(_state: &'a AppState, job: &'a WorkflowJob) -> StepFuture<'a> {
+    Box::pin(async move {
+        let email: String = context_field(job, "email")?;
+        MailerService::send_welcome_email(&email)
+            .await
+            .map(|_| serde_json::Value::Null)
+            .map_err(|e| e.to_string())
+    })
+}
+
+fn context_field<T: DeserializeOwned>(job: &WorkflowJob, key: &str) -> Result<T, String> {
+    job.context
+        .get(key)
+        .cloned()
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or_else(|| format!("The workflow context has no valid `{key}`"))
+}
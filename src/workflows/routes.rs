@@ -0,0 +1,90 @@
+use actix_failwrap::proof_route;
+use actix_web::{
+    HttpResponse, Result,
+    web::{self, Data, Json, Path, Query},
+};
+use actix_web_grants::authorities::{AuthDetails, AuthoritiesCheck};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    users::permissions::{WORKFLOWS_READ, WORKFLOWS_WRITE},
+    workflows::{
+        ListWorkflowsQuery, StartWorkflowRequest, WorkflowService, errors::workflow::WorkflowErrors,
+    },
+};
+
+/// Configure workflow admin routes, mounted under the authenticated `/admin` scope
+///
+/// Listing requires the `workflows:read` permission, starting `workflows:write`
+///
+/// `POST` `/admin/workflows` - Start a workflow instance, whose steps are run by the worker
+///
+/// Start Workflow Request entity:
+/// ```ignore
+/// #[derive(Debug, Validate, Deserialize)]
+/// pub struct StartWorkflowRequest {
+///     #[validate(length(min = 1, max = 100))]
+///     pub workflow: String,
+///     /// Initial context, `{}` when omitted
+///     #[serde(default = "empty_context")]
+///     pub context: serde_json::Value,
+/// }
+/// ```
+///
+/// `GET` `/admin/workflows` - List workflow instances, newest first
+///
+/// Query parameters: `status`, `workflow`, `limit` (1-100, default 50) and `offset`
+///
+/// `GET` `/admin/workflows/{id}` - Get a single workflow instance
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(start_workflow)
+        .service(list_workflows)
+        .service(get_workflow);
+}
+
+#[proof_route("POST /workflows")]
+async fn start_workflow(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    #[error_override(InvalidRequest)] body: Json<StartWorkflowRequest>,
+) -> Result<HttpResponse, WorkflowErrors> {
+    if !auth.has_authority(WORKFLOWS_WRITE) {
+        return Err(WorkflowErrors::Forbidden);
+    }
+
+    body.validate()
+        .map_err(|_| WorkflowErrors::InvalidRequest)?;
+    let body = body.into_inner();
+    let instance = WorkflowService::start(&state, &body.workflow, body.context).await?;
+    Ok(HttpResponse::Created().json(instance))
+}
+
+#[proof_route("GET /workflows")]
+async fn list_workflows(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    query: Query<ListWorkflowsQuery>,
+) -> Result<HttpResponse, WorkflowErrors> {
+    if !auth.has_authority(WORKFLOWS_READ) {
+        return Err(WorkflowErrors::Forbidden);
+    }
+
+    let instances = WorkflowService::list(&state, &query).await?;
+    Ok(HttpResponse::Ok().json(instances))
+}
+
+#[proof_route("GET /workflows/{id}")]
+async fn get_workflow(
+    auth: AuthDetails,
+    state: Data<AppState>,
+    id: Path<Uuid>,
+) -> Result<HttpResponse, WorkflowErrors> {
+    if !auth.has_authority(WORKFLOWS_READ) {
+        return Err(WorkflowErrors::Forbidden);
+    }
+
+    let instance = WorkflowService::get(&state, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(instance))
+}
@@ -0,0 +1,462 @@
+use std::time::Duration;
+
+use sqlx::{PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    queues::{QueueService, entities::QueueMessage, payload},
+    workflows::{
+        DEFINITIONS, ListWorkflowsQuery, WorkflowDefinition, WorkflowStep,
+        entities::{
+            STATUS_COMPENSATED, STATUS_COMPENSATING, STATUS_COMPLETED, STATUS_FAILED,
+            STATUS_RUNNING, WorkflowInstance, WorkflowJob,
+        },
+        errors::workflow::WorkflowErrors,
+    },
+};
+
+/// Seconds a job stays hidden from other workers while its handler runs.
+const JOB_VISIBILITY_TIMEOUT_SECS: i32 = 60;
+/// Jobs read from a step queue at once.
+const JOB_BATCH_SIZE: i32 = 10;
+/// Wait before polling again when every step queue was empty.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct WorkflowService;
+
+impl WorkflowService {
+    pub fn definition(name: &str) -> Result<&'static WorkflowDefinition, WorkflowErrors> {
+        DEFINITIONS
+            .iter()
+            .find(|definition| definition.name == name)
+            .copied()
+            .ok_or(WorkflowErrors::UnknownWorkflow)
+    }
+
+    /// Creates a workflow instance and enqueues its first step.
+    pub async fn start(
+        state: &AppState,
+        workflow_name: &str,
+        context: serde_json::Value,
+    ) -> Result<WorkflowInstance, WorkflowErrors> {
+        let definition = Self::definition(workflow_name)?;
+        if definition.steps.is_empty() {
+            return Err(WorkflowErrors::UnknownStep);
+        }
+
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?;
+
+        let instance = sqlx::query_as::<_, WorkflowInstance>(
+            "INSERT INTO workflow_instances (workflow_name, status, current_step, context)
+             VALUES ($1, $2, 0, $3)
+             RETURNING *",
+        )
+        .bind(definition.name)
+        .bind(STATUS_RUNNING)
+        .bind(&context)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| WorkflowErrors::DatabaseError)?;
+
+        Self::enqueue(state, &mut tx, definition, &instance, 0, false).await?;
+
+        tx.commit()
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?;
+        Ok(instance)
+    }
+
+    /// Runs the jobs of every workflow step, polling until the process exits.
+    pub async fn run_worker(state: AppState) {
+        let queues = DEFINITIONS.iter().flat_map(|definition| {
+            definition.steps.iter().flat_map(|step| {
+                [Some(step.queue), step.compensation_queue]
+                    .into_iter()
+                    .flatten()
+                    .map(move |queue| (queue, step))
+            })
+        });
+        let queues: Vec<(&str, &WorkflowStep)> = queues.collect();
+
+        for (queue, _) in &queues {
+            if let Err(e) = QueueService::create(&state, queue).await {
+                eprintln!("Failed to create workflow queue {queue}: {e}");
+            }
+        }
+
+        loop {
+            let mut processed = 0;
+            for (queue, step) in &queues {
+                match Self::run_jobs(&state, queue, step).await {
+                    Ok(count) => processed += count,
+                    Err(e) => eprintln!("Failed to run workflow jobs of {queue}: {e}"),
+                }
+            }
+
+            if processed == 0 {
+                actix_web::rt::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Runs a batch of jobs from `queue` with the handler of `step` and reports each
+    /// outcome. Messages that are not jobs are archived.
+    async fn run_jobs(
+        state: &AppState,
+        queue: &str,
+        step: &WorkflowStep,
+    ) -> Result<usize, WorkflowErrors> {
+        let messages =
+            QueueService::read(state, queue, JOB_VISIBILITY_TIMEOUT_SECS, JOB_BATCH_SIZE)
+                .await
+                .map_err(|_| WorkflowErrors::QueueError)?;
+
+        for message in &messages {
+            let Ok(job) = serde_json::from_value::<WorkflowJob>(message.message.clone()) else {
+                eprintln!(
+                    "Archiving malformed workflow job {} of {queue}",
+                    message.msg_id
+                );
+                if let Err(e) = QueueService::archive(state, queue, message.msg_id).await {
+                    eprintln!("Failed to archive workflow job {}: {e}", message.msg_id);
+                }
+                continue;
+            };
+
+            let result = match (step.handler)(state, &job).await {
+                Ok(output) => Self::complete_step(state, queue, message.msg_id, &job, output).await,
+                Err(error) => Self::fail_step(state, queue, message, &job, &error).await,
+            };
+            if let Err(e) = result {
+                eprintln!(
+                    "Failed to report workflow job {} of {queue}: {e}",
+                    message.msg_id
+                );
+            }
+        }
+
+        Ok(messages.len())
+    }
+
+    /// Records the output of a finished job and moves the workflow forward.
+    ///
+    /// Objects returned by a step are merged into the workflow context, so later steps
+    /// see everything produced before them. The instance is locked while the next job is
+    /// enqueued and the finished one deleted, in one transaction, so a job redelivered
+    /// after a crash or finished twice no longer matches the instance and is
+    /// acknowledged without side effects.
+    pub async fn complete_step(
+        state: &AppState,
+        queue: &str,
+        msg_id: i64,
+        job: &WorkflowJob,
+        output: serde_json::Value,
+    ) -> Result<(), WorkflowErrors> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?;
+        let instance = Self::lock(&mut tx, job.instance_id).await?;
+
+        if Self::is_current(&instance, job) {
+            let definition = Self::definition(&instance.workflow_name)?;
+            let context = Self::merge_context(instance.context.clone(), job, output);
+
+            let next = if job.compensation {
+                Self::previous_compensable_step(definition, job.step)
+            } else {
+                Some(job.step + 1).filter(|step| (*step as usize) < definition.steps.len())
+            };
+
+            match next {
+                Some(step) => {
+                    let instance =
+                        Self::update(&mut tx, instance.id, &instance.status, step, &context, None)
+                            .await?;
+                    Self::enqueue(
+                        state,
+                        &mut tx,
+                        definition,
+                        &instance,
+                        step,
+                        job.compensation,
+                    )
+                    .await?;
+                }
+                None => {
+                    let status = if job.compensation {
+                        STATUS_COMPENSATED
+                    } else {
+                        STATUS_COMPLETED
+                    };
+                    Self::update(&mut tx, instance.id, status, job.step, &context, None).await?;
+                }
+            }
+        }
+
+        Self::acknowledge(state, tx, queue, msg_id).await
+    }
+
+    /// Records a failed attempt of a job.
+    ///
+    /// The message is left in the queue to be retried once its visibility timeout
+    /// expires. When the step has used all of its attempts the message is moved to the
+    /// dead letter queue and the completed steps are compensated in reverse order, in the
+    /// same transaction that locks the instance.
+    pub async fn fail_step(
+        state: &AppState,
+        queue: &str,
+        message: &QueueMessage,
+        job: &WorkflowJob,
+        error: &str,
+    ) -> Result<(), WorkflowErrors> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?;
+        let instance = Self::lock(&mut tx, job.instance_id).await?;
+
+        if !Self::is_current(&instance, job) {
+            return Self::acknowledge(state, tx, queue, message.msg_id).await;
+        }
+
+        let definition = Self::definition(&instance.workflow_name)?;
+        let step = definition
+            .steps
+            .get(job.step as usize)
+            .ok_or(WorkflowErrors::UnknownStep)?;
+
+        if message.read_ct < step.max_attempts {
+            Self::update(
+                &mut tx,
+                instance.id,
+                &instance.status,
+                instance.current_step,
+                &instance.context,
+                Some(error),
+            )
+            .await?;
+            return tx.commit().await.map_err(|_| WorkflowErrors::DatabaseError);
+        }
+
+        QueueService::move_to_dead_letter_in(&mut tx, queue, message.msg_id)
+            .await
+            .map_err(|_| WorkflowErrors::QueueError)?;
+
+        let compensation = if job.compensation {
+            None
+        } else {
+            Self::previous_compensable_step(definition, job.step)
+        };
+
+        match compensation {
+            Some(compensation_step) => {
+                let instance = Self::update(
+                    &mut tx,
+                    instance.id,
+                    STATUS_COMPENSATING,
+                    compensation_step,
+                    &instance.context,
+                    Some(error),
+                )
+                .await?;
+                Self::enqueue(
+                    state,
+                    &mut tx,
+                    definition,
+                    &instance,
+                    compensation_step,
+                    true,
+                )
+                .await?;
+            }
+            None => {
+                Self::update(
+                    &mut tx,
+                    instance.id,
+                    STATUS_FAILED,
+                    job.step,
+                    &instance.context,
+                    Some(error),
+                )
+                .await?;
+            }
+        }
+
+        tx.commit().await.map_err(|_| WorkflowErrors::DatabaseError)
+    }
+
+    pub async fn get(state: &AppState, id: Uuid) -> Result<WorkflowInstance, WorkflowErrors> {
+        sqlx::query_as::<_, WorkflowInstance>("SELECT * FROM workflow_instances WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db_pool)
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?
+            .ok_or(WorkflowErrors::InstanceNotFound)
+    }
+
+    /// Reads an instance and locks it until the end of the transaction.
+    async fn lock(conn: &mut PgConnection, id: Uuid) -> Result<WorkflowInstance, WorkflowErrors> {
+        sqlx::query_as::<_, WorkflowInstance>(
+            "SELECT * FROM workflow_instances WHERE id = $1 FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| WorkflowErrors::DatabaseError)?
+        .ok_or(WorkflowErrors::InstanceNotFound)
+    }
+
+    pub async fn list(
+        state: &AppState,
+        query: &ListWorkflowsQuery,
+    ) -> Result<Vec<WorkflowInstance>, WorkflowErrors> {
+        sqlx::query_as::<_, WorkflowInstance>(
+            "SELECT * FROM workflow_instances
+             WHERE ($1::TEXT IS NULL OR status = $1)
+               AND ($2::TEXT IS NULL OR workflow_name = $2)
+             ORDER BY created_at DESC
+             LIMIT $3 OFFSET $4",
+        )
+        .bind(&query.status)
+        .bind(&query.workflow)
+        .bind(query.limit.unwrap_or(50).clamp(1, 100))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(|_| WorkflowErrors::DatabaseError)
+    }
+
+    fn is_current(instance: &WorkflowInstance, job: &WorkflowJob) -> bool {
+        let expected_status = if job.compensation {
+            STATUS_COMPENSATING
+        } else {
+            STATUS_RUNNING
+        };
+        instance.status == expected_status && instance.current_step == job.step
+    }
+
+    fn previous_compensable_step(definition: &WorkflowDefinition, before: i32) -> Option<i32> {
+        (0..before).rev().find(|step| {
+            definition.steps[*step as usize]
+                .compensation_queue
+                .is_some()
+        })
+    }
+
+    fn merge_context(
+        mut context: serde_json::Value,
+        job: &WorkflowJob,
+        output: serde_json::Value,
+    ) -> serde_json::Value {
+        match (&mut context, output) {
+            (_, serde_json::Value::Null) => {}
+            (serde_json::Value::Object(context), serde_json::Value::Object(output)) => {
+                context.extend(output);
+            }
+            (serde_json::Value::Object(context), output) => {
+                context.insert(job.step_name.clone(), output);
+            }
+            (context, output) => *context = output,
+        }
+        context
+    }
+
+    async fn update(
+        conn: &mut PgConnection,
+        id: Uuid,
+        status: &str,
+        current_step: i32,
+        context: &serde_json::Value,
+        last_error: Option<&str>,
+    ) -> Result<WorkflowInstance, WorkflowErrors> {
+        sqlx::query_as::<_, WorkflowInstance>(
+            "UPDATE workflow_instances
+             SET status = $2,
+                 current_step = $3,
+                 context = $4,
+                 last_error = COALESCE($5, last_error),
+                 finished_at = CASE WHEN $2 IN ('completed', 'compensated', 'failed')
+                                    THEN NOW() END
+             WHERE id = $1
+             RETURNING *",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(current_step)
+        .bind(context)
+        .bind(last_error)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|_| WorkflowErrors::DatabaseError)
+    }
+
+    /// Sends the job of `step` on `conn`, creating its queue if needed.
+    async fn enqueue(
+        state: &AppState,
+        conn: &mut PgConnection,
+        definition: &WorkflowDefinition,
+        instance: &WorkflowInstance,
+        step: i32,
+        compensation: bool,
+    ) -> Result<i64, WorkflowErrors> {
+        let workflow_step = definition
+            .steps
+            .get(step as usize)
+            .ok_or(WorkflowErrors::UnknownStep)?;
+        let queue = if compensation {
+            workflow_step
+                .compensation_queue
+                .ok_or(WorkflowErrors::UnknownStep)?
+        } else {
+            workflow_step.queue
+        };
+
+        let job = WorkflowJob {
+            instance_id: instance.id,
+            workflow: definition.name.to_string(),
+            step,
+            step_name: workflow_step.name.to_string(),
+            compensation,
+            context: instance.context.clone(),
+        };
+        let message = serde_json::to_value(&job).map_err(|_| WorkflowErrors::QueueError)?;
+
+        QueueService::create(state, queue)
+            .await
+            .map_err(|_| WorkflowErrors::QueueError)?;
+        QueueService::send_in(state, conn, queue, &message)
+            .await
+            .map_err(|_| WorkflowErrors::QueueError)
+    }
+
+    /// Deletes a handled job with the rest of the transaction, then its offloaded payload.
+    async fn acknowledge(
+        state: &AppState,
+        mut tx: Transaction<'_, Postgres>,
+        queue: &str,
+        msg_id: i64,
+    ) -> Result<(), WorkflowErrors> {
+        let message = QueueService::delete_in(&mut tx, queue, msg_id)
+            .await
+            .map_err(|_| WorkflowErrors::QueueError)?;
+        tx.commit()
+            .await
+            .map_err(|_| WorkflowErrors::DatabaseError)?;
+
+        if let Some(message) = message {
+            payload::delete_blob(state, &message)
+                .await
+                .map_err(|_| WorkflowErrors::QueueError)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! OpenID Connect login against a mock provider serving discovery, JWKS and token
+//! endpoints on a local port.
+//!
+//! `sqlx::test` runs the migrations in a new database, so `DATABASE_URL` must point to a
+//! server where the user can create databases.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{App, HttpResponse, HttpServer, web};
+use backend::{
+    AppState,
+    audit::AuditThrottle,
+    middlewares::revocation::RevocationCache,
+    oidc::{OidcService, ProviderCache, errors::oidc::OidcErrors},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+const PROVIDER: &str = "mock";
+const CLIENT_ID: &str = "backend-tests";
+const CODE: &str = "authorization-code";
+const KEY_ID: &str = "mock-key";
+const PRIVATE_KEY: &[u8] = include_bytes!("fixtures/oidc_provider_ed25519.pem");
+/// `x` of the public key of `PRIVATE_KEY`
+const PUBLIC_KEY_X: &str = "3Jig7L4YN0axc1vPaXsft_LvzTVb4h7lxy1m724jIBE";
+
+/// What the mock provider knows about the login in progress.
+#[derive(Default)]
+struct Login {
+    nonce: String,
+    code_challenge: String,
+    subject: String,
+    email: String,
+    email_verified: bool,
+}
+
+struct MockProvider {
+    issuer: String,
+    login: Arc<Mutex<Login>>,
+}
+
+impl MockProvider {
+    /// Serves the provider from its own thread and actix system.
+    fn start() -> Self {
+        let login = Arc::new(Mutex::new(Login::default()));
+        let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+
+        let shared = login.clone();
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(web::Data::new(shared.clone()))
+                        .route(
+                            "/.well-known/openid-configuration",
+                            web::get().to(discovery),
+                        )
+                        .route("/jwks", web::get().to(jwks))
+                        .route("/token", web::post().to(token))
+                })
+                .workers(1)
+                .bind(("127.0.0.1", 0))
+                .expect("Failed to bind the mock provider");
+                addr_tx
+                    .send(server.addrs()[0])
+                    .expect("Failed to report the mock provider address");
+                server.run().await
+            })
+        });
+
+        let addr = addr_rx.recv().expect("The mock provider did not start");
+        MockProvider {
+            issuer: format!("http://{addr}"),
+            login,
+        }
+    }
+
+    /// Starts a login at the service and records what the provider would receive.
+    async fn authorize(&self, state: &AppState, subject: &str, email: &str) -> String {
+        let url = OidcService::authorization_url(state, PROVIDER)
+            .await
+            .expect("Failed to build the authorization URL");
+        let url = reqwest::Url::parse(&url).unwrap();
+        assert!(
+            url.as_str()
+                .starts_with(&format!("{}/authorize", self.issuer))
+        );
+
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["code_challenge_method"], "S256");
+
+        *self.login.lock().unwrap() = Login {
+            nonce: query["nonce"].clone(),
+            code_challenge: query["code_challenge"].clone(),
+            subject: subject.to_string(),
+            email: email.to_string(),
+            email_verified: true,
+        };
+        query["state"].clone()
+    }
+}
+
+type SharedLogin = web::Data<Arc<Mutex<Login>>>;
+
+async fn discovery(req: actix_web::HttpRequest) -> HttpResponse {
+    let issuer = format!("http://{}", req.connection_info().host());
+    HttpResponse::Ok().json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+    }))
+}
+
+async fn jwks() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "use": "sig",
+            "alg": "EdDSA",
+            "kid": KEY_ID,
+            "x": PUBLIC_KEY_X,
+        }]
+    }))
+}
+
+/// Exchanges `CODE` for an ID token when the PKCE verifier matches the challenge.
+async fn token(
+    req: actix_web::HttpRequest,
+    login: SharedLogin,
+    form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    let login = login.lock().unwrap();
+    let verifier = form.get("code_verifier").map(String::as_str).unwrap_or("");
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+        || form.get("code").map(String::as_str) != Some(CODE)
+        || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        || challenge != login.code_challenge
+    {
+        return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "iss": format!("http://{}", req.connection_info().host()),
+        "aud": CLIENT_ID,
+        "sub": login.subject,
+        "email": login.email,
+        "email_verified": login.email_verified,
+        "nonce": login.nonce,
+        "iat": now,
+        "exp": now + 300,
+    });
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KEY_ID.to_string());
+    let key = EncodingKey::from_ed_pem(PRIVATE_KEY).unwrap();
+    let id_token = encode(&header, &claims, &key).unwrap();
+
+    HttpResponse::Ok().json(json!({
+        "access_token": "unused",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+}
+
+/// Points the service at the mock provider. Every test of this file runs in one function,
+/// so no other thread reads the environment meanwhile.
+fn configure(issuer: &str) {
+    let vars = [
+        ("SECRET_KEY", "integration-test-secret"),
+        ("FRONTEND_URL", "http://localhost:3000"),
+        ("SMTP_HOST", "localhost"),
+        ("SMTP_PORT", "1025"),
+        ("SMTP_USERNAME", ""),
+        ("SMTP_PASSWORD", ""),
+        ("SMTP_FROM_EMAIL", "noreply@example.com"),
+        ("SMTP_FROM_NAME", "Tests"),
+        ("ARGON2_MEMORY_KIB", "8"),
+        ("ARGON2_ITERATIONS", "1"),
+        ("OIDC_PROVIDERS", PROVIDER),
+        ("OIDC_MOCK_ISSUER", issuer),
+        ("OIDC_MOCK_CLIENT_ID", CLIENT_ID),
+        (
+            "OIDC_MOCK_REDIRECT_URI",
+            "http://localhost:3000/oidc/mock/callback",
+        ),
+        ("OIDC_DEFAULT_ROLE", "user"),
+    ];
+    for (key, value) in vars {
+        // SAFETY: set before the service reads any configuration, see above
+        unsafe { std::env::set_var(key, value) };
+    }
+}
+
+#[sqlx::test]
+async fn complete_login_links_and_provisions_users(pool: PgPool) {
+    let provider = MockProvider::start();
+    configure(&provider.issuer);
+    sqlx::query("INSERT INTO catalogs.roles (name) VALUES ('user')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let state = AppState {
+        db_pool: pool.clone(),
+        revocations: Arc::new(RevocationCache::default()),
+        oidc: Arc::new(ProviderCache::default()),
+        audit_throttle: Arc::new(AuditThrottle::default()),
+    };
+
+    // An unknown subject with a verified email provisions a user
+    let login_state = provider
+        .authorize(&state, "subject-1", "oidc-user@example.com")
+        .await;
+    let user_id = OidcService::complete_login(&state, PROVIDER, CODE, &login_state)
+        .await
+        .expect("First login failed");
+
+    let (email, verified, role): (String, bool, String) = sqlx::query_as(
+        "SELECT u.email, u.email_verified_at IS NOT NULL, r.name
+         FROM users u
+         JOIN users_role ur ON ur.user_id = u.id
+         JOIN catalogs.roles r ON r.id = ur.role_id
+         WHERE u.id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(email, "oidc-user@example.com");
+    assert!(verified);
+    assert_eq!(role, "user");
+
+    let identities = OidcService::identities(&state, user_id).await.unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].provider, PROVIDER);
+    assert_eq!(identities[0].subject, "subject-1");
+
+    // A login state works once
+    assert!(matches!(
+        OidcService::complete_login(&state, PROVIDER, CODE, &login_state).await,
+        Err(OidcErrors::InvalidState)
+    ));
+
+    // The linked subject logs in as the same user, whatever email it now reports
+    let login_state = provider
+        .authorize(&state, "subject-1", "renamed@example.com")
+        .await;
+    let again = OidcService::complete_login(&state, PROVIDER, CODE, &login_state)
+        .await
+        .expect("Second login failed");
+    assert_eq!(again, user_id);
+
+    // A code the provider rejects
+    let login_state = provider
+        .authorize(&state, "subject-1", "oidc-user@example.com")
+        .await;
+    assert!(matches!(
+        OidcService::complete_login(&state, PROVIDER, "stolen-code", &login_state).await,
+        Err(OidcErrors::CodeRejected)
+    ));
+
+    // An ID token issued for another login, whose nonce does not match
+    let login_state = provider
+        .authorize(&state, "subject-1", "oidc-user@example.com")
+        .await;
+    provider.login.lock().unwrap().nonce = "another-nonce".to_string();
+    assert!(matches!(
+        OidcService::complete_login(&state, PROVIDER, CODE, &login_state).await,
+        Err(OidcErrors::InvalidIdToken)
+    ));
+
+    // A new subject is only linked by email when the provider verified it
+    let login_state = provider
+        .authorize(&state, "subject-2", "oidc-user@example.com")
+        .await;
+    provider.login.lock().unwrap().email_verified = false;
+    assert!(matches!(
+        OidcService::complete_login(&state, PROVIDER, CODE, &login_state).await,
+        Err(OidcErrors::EmailNotVerified)
+    ));
+
+    let login_state = provider
+        .authorize(&state, "subject-2", "oidc-user@example.com")
+        .await;
+    let linked = OidcService::complete_login(&state, PROVIDER, CODE, &login_state)
+        .await
+        .expect("Linking a second subject failed");
+    assert_eq!(linked, user_id);
+    assert_eq!(
+        OidcService::identities(&state, user_id)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}